@@ -0,0 +1,323 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Actions on a selected result
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use anyhow::{anyhow, Context};
+use dbus::blocking::Connection;
+use dbus::Message;
+use url::Url;
+
+use crate::child;
+use crate::clipboard;
+use crate::config::Config;
+use crate::flow;
+use crate::history;
+use crate::notify;
+use crate::tracker::DBUS_TIMEOUT;
+
+/* rofi's ROFI_RETV values for kb-custom-1 to kb-custom-19 */
+const RETV_CUSTOM_BASE: u32 = 9;
+pub const CUSTOM_KEYS: u32 = 19;
+
+/* the xattr used for tags, shared with other desktop tools */
+const TAGS_ATTR: &str = "xattr::xdg.tags";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Open,
+    OpenFolder,
+    Reveal,
+    CopyPath,
+    CopyUri,
+    /* copy the text around the match, from the search */
+    CopySnippet,
+    Trash,
+    Tag,
+    /* choose the default application for the file's type */
+    DefaultApp,
+    /* compose an email with the file attached */
+    Email,
+    /* add to, or remove from, the set of selected results */
+    Select,
+    /* pin to (or unpin from) the top of the results, for the session */
+    Pin,
+    /* write the current result set to the export file */
+    Export,
+    /* a user-defined command, as a list of argument templates */
+    Command(Vec<String>),
+}
+
+impl Action {
+    pub fn from_name(name: &str, commands: &[(String, Vec<String>)])
+            -> Option<Action> {
+        let a = match name {
+            "open" => Action::Open,
+            "open-folder" => Action::OpenFolder,
+            "reveal" => Action::Reveal,
+            "copy-path" => Action::CopyPath,
+            "copy-uri" => Action::CopyUri,
+            "copy-snippet" => Action::CopySnippet,
+            "trash" => Action::Trash,
+            "tag" => Action::Tag,
+            "default-app" => Action::DefaultApp,
+            "email" => Action::Email,
+            "select" => Action::Select,
+            "pin" => Action::Pin,
+            "export" => Action::Export,
+            _ => {
+                let (_, argv) = commands.iter().find(|(n, _)| n == name)?;
+                Action::Command(argv.clone())
+            }
+        };
+        Some(a)
+    }
+
+    /* run the action on a result. Actions that need further input return
+     * the flow state to continue with */
+    pub fn run(&self, uri: &str, config: &Config)
+            -> anyhow::Result<Option<flow::State>> {
+        match self {
+            Action::Open => open_uri(uri, config)?,
+            Action::OpenFolder => {
+                let dir = Url::from_directory_path(parent_dir(uri)?)
+                    .map_err(|_| anyhow!("invalid directory"))?;
+                open_uri(dir.as_str(), config)?
+            }
+            Action::Reveal => reveal(uri)?,
+            Action::CopyPath => {
+                let path = file_path(uri)?;
+                clipboard::copy(&path.to_string_lossy(), config.clipboard)?
+            }
+            Action::CopyUri => clipboard::copy(uri, config.clipboard)?,
+            Action::Trash => run_command(Command::new("gio").args(["trash", uri]))?,
+            Action::Tag => {
+                return Ok(Some(flow::State::new("tag").with("uri", uri)));
+            }
+            Action::DefaultApp => {
+                return Ok(Some(flow::State::new("default-app").with("uri", uri)));
+            }
+            Action::Email => {
+                let path = file_path(uri)?;
+                spawn_detached(Command::new("xdg-email").arg("--attach").arg(path))?
+            }
+            /* these act on the search state, rather than a single result */
+            Action::Select | Action::Pin | Action::Export | Action::CopySnippet => (),
+            Action::Command(argv) => run_template(argv, uri, &[])?,
+        }
+        Ok(None)
+    }
+}
+
+/* the action bound to a kb-custom key, from rofi's ROFI_RETV value */
+pub fn for_retv(retv: u32, keys: &HashMap<u32, Action>) -> Option<&Action> {
+    let key = retv.checked_sub(RETV_CUSTOM_BASE)?;
+    keys.get(&key)
+}
+
+pub fn file_path(uri: &str) -> anyhow::Result<PathBuf> {
+    Url::parse(uri)
+        .ok()
+        .and_then(|u| u.to_file_path().ok())
+        .ok_or_else(|| anyhow!("'{}' is not a local file", uri))
+}
+
+fn parent_dir(uri: &str) -> anyhow::Result<PathBuf> {
+    let path = file_path(uri)?;
+    path.parent()
+        .map(|p| p.to_path_buf())
+        .ok_or_else(|| anyhow!("'{}' has no parent directory", uri))
+}
+
+/* placeholders in command templates */
+const PLACEHOLDERS: &[&str] = &["{uri}", "{path}", "{dir}"];
+
+/* whether a template argument has only known placeholders: the standard
+ * ones, plus any extra for the type of template */
+pub fn valid_template(arg: &str, extra: &[&str]) -> bool {
+    let rest = PLACEHOLDERS
+        .iter()
+        .chain(extra)
+        .fold(arg.to_string(), |a, p| a.replace(p, ""));
+    !rest.contains('{') && !rest.contains('}')
+}
+
+/* substitute placeholders in each argument of a command template. Since
+ * we don't use a shell, substituted values need no quoting. Paths may not
+ * be UTF-8, so arguments are built as OsStrings */
+fn expand_template(argv: &[String], uri: &str, extra: &[(&str, &OsStr)])
+        -> anyhow::Result<Vec<OsString>> {
+    if argv.is_empty() {
+        return Err(anyhow!("empty command"));
+    }
+
+    let needs_path = argv.iter().any(|a| a.contains("{path}") || a.contains("{dir}"));
+    let (path, dir) = if needs_path {
+        (file_path(uri)?.into_os_string(), parent_dir(uri)?.into_os_string())
+    } else {
+        (OsString::new(), OsString::new())
+    };
+
+    let mut values: Vec<(&str, &OsStr)> =
+        vec![("{uri}", OsStr::new(uri)), ("{path}", &path), ("{dir}", &dir)];
+    values.extend(extra);
+
+    Ok(argv.iter().map(|a| expand_arg(a, &values)).collect())
+}
+
+/* run a command template on a uri, with any extra placeholder values */
+pub fn run_template(argv: &[String], uri: &str, extra: &[(&str, &OsStr)])
+        -> anyhow::Result<()> {
+    let args = expand_template(argv, uri, extra)?;
+    spawn_detached(Command::new(&args[0]).args(&args[1..]))
+}
+
+fn expand_arg(arg: &str, values: &[(&str, &OsStr)]) -> OsString {
+    let mut res = OsString::new();
+    let mut rest = arg;
+
+    loop {
+        /* the earliest placeholder in the rest of the argument */
+        let next = values
+            .iter()
+            .filter_map(|(p, v)| rest.find(p).map(|i| (i, p, v)))
+            .min_by_key(|(i, _, _)| *i);
+
+        match next {
+            Some((i, p, v)) => {
+                res.push(&rest[..i]);
+                res.push(v);
+                rest = &rest[i + p.len()..];
+            }
+            None => {
+                res.push(rest);
+                return res;
+            }
+        }
+    }
+}
+
+/* open a uri in a detached child, so rofi doesn't wait for the handler.
+ * Failures are reported by notification, as is a handler that hasn't
+ * finished starting within the configured timeout */
+pub fn open_uri(uri: &str, config: &Config) -> anyhow::Result<()> {
+    if config.records_opens() {
+        let _ = history::record_open(uri);
+    }
+    child::detach(|| open_reporting(uri, config.open_timeout))
+}
+
+fn open_reporting(uri: &str, timeout: Option<Duration>) {
+    let (tx, rx) = mpsc::channel();
+    let u = uri.to_string();
+    thread::spawn(move || {
+        let _ = tx.send(opener::open(&u));
+    });
+
+    let res = match timeout {
+        Some(t) => match rx.recv_timeout(t) {
+            Ok(r) => Some(r),
+            Err(mpsc::RecvTimeoutError::Timeout) => {
+                let name = file_path(uri)
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_else(|_| uri.to_string());
+                let body = format!("No response from the application for {} \
+                                    after {} seconds", name, t.as_secs());
+                let _ = notify::notify("Still opening file", &body);
+                rx.recv().ok()
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => None,
+        },
+        None => rx.recv().ok(),
+    };
+
+    if let Some(Err(e)) = res {
+        let _ = notify::notify("Can't open file", &notify::open_error(&e));
+    }
+}
+
+/* start a command without waiting for it. Its stdio must not refer to our
+ * stdout, or rofi will wait for it to exit */
+fn spawn_detached(cmd: &mut Command) -> anyhow::Result<()> {
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("can't run {:?}", cmd.get_program()))?;
+    Ok(())
+}
+
+fn run_command(cmd: &mut Command) -> anyhow::Result<()> {
+    let status = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .with_context(|| format!("can't run {:?}", cmd.get_program()))?;
+
+    if !status.success() {
+        return Err(anyhow!("{:?} failed: {}", cmd.get_program(), status));
+    }
+    Ok(())
+}
+
+/* show the file in the file manager */
+fn reveal(uri: &str) -> anyhow::Result<()> {
+    let conn = Connection::new_session()?;
+    let msg = Message::new_method_call("org.freedesktop.FileManager1",
+            "/org/freedesktop/FileManager1",
+            "org.freedesktop.FileManager1",
+            "ShowItems")
+        .map_err(|e| anyhow!(e))?
+        .append2(vec![uri], "");
+
+    conn.channel().send_with_reply_and_block(msg, DBUS_TIMEOUT)?;
+    Ok(())
+}
+
+pub fn tags(uri: &str) -> Vec<String> {
+    let output = Command::new("gio")
+        .args(["info", "--attributes", TAGS_ATTR, uri])
+        .stderr(Stdio::null())
+        .output();
+
+    let output = match output {
+        Ok(o) if o.status.success() => o.stdout,
+        _ => return Vec::new(),
+    };
+
+    let prefix = format!("  {}: ", TAGS_ATTR);
+
+    String::from_utf8_lossy(&output)
+        .lines()
+        .find_map(|l| l.strip_prefix(&prefix))
+        .map(|t| t
+            .split(',')
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(str::to_string)
+            .collect())
+        .unwrap_or_default()
+}
+
+pub fn add_tag(uri: &str, tag: &str) -> anyhow::Result<()> {
+    let tag = tag.trim().replace(',', " ");
+    if tag.is_empty() {
+        return Ok(());
+    }
+
+    let mut tags = tags(uri);
+    if !tags.contains(&tag) {
+        tags.push(tag);
+    }
+
+    run_command(Command::new("gio")
+        .args(["set", uri, TAGS_ATTR, &tags.join(",")]))
+}
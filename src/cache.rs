@@ -0,0 +1,225 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Cache of query results, kept fresh by a daemon process
+ *
+ * Results are only cached while a daemon (tracker-rofi --daemon) is running.
+ * The daemon watches for Tracker's GraphUpdated signals, and refreshes the
+ * cached results when the data changes. Without a daemon, we have no way to
+ * know when cached results are stale, so always query the endpoint directly.
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use anyhow::Context;
+use dbus::blocking::Connection;
+use dbus::message::MatchRule;
+
+use crate::tracker;
+
+/* maximum number of cached queries; the least-recently-stored are pruned */
+const CACHE_MAX_ENTRIES: usize = 100;
+
+/* updates tend to arrive in bursts, so wait for this period without any
+ * further updates before refreshing */
+const UPDATE_SETTLE_TIME: Duration = Duration::from_millis(500);
+
+pub struct Cache {
+    dir: PathBuf,
+}
+
+fn cache_dir() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))?;
+    Some(dir.join("tracker-rofi"))
+}
+
+fn comm(pid: &str) -> Option<String> {
+    fs::read_to_string(format!("/proc/{}/comm", pid)).ok()
+}
+
+/* is the process in the pidfile a tracker-rofi daemon? */
+fn daemon_running(dir: &Path) -> bool {
+    let pid = match fs::read_to_string(dir.join("daemon.pid")) {
+        Ok(p) => p,
+        Err(_) => return false,
+    };
+
+    match (comm(pid.trim()), comm("self")) {
+        (Some(a), Some(b)) => a == b,
+        _ => false,
+    }
+}
+
+/* entries are stored as: query NUL n-columns NUL column-names... raw-cursor,
+ * with each column name NUL-terminated */
+fn encode(query: &str, columns: &[String], buf: &[u8]) -> Vec<u8> {
+    let mut v = Vec::new();
+    v.extend(query.as_bytes());
+    v.push(0);
+    v.extend(columns.len().to_string().as_bytes());
+    v.push(0);
+    for c in columns {
+        v.extend(c.as_bytes());
+        v.push(0);
+    }
+    v.extend(buf);
+    v
+}
+
+fn decode(data: &[u8]) -> Option<(String, Vec<String>, Vec<u8>)> {
+    let mut fields = data.splitn(3, |b| *b == 0);
+    let query = std::str::from_utf8(fields.next()?).ok()?.to_string();
+    let n: usize = std::str::from_utf8(fields.next()?).ok()?.parse().ok()?;
+    let mut rest = fields.next()?;
+
+    let mut columns = Vec::new();
+    for _ in 0..n {
+        let mut f = rest.splitn(2, |b| *b == 0);
+        columns.push(std::str::from_utf8(f.next()?).ok()?.to_string());
+        rest = f.next()?;
+    }
+
+    Some((query, columns, rest.to_vec()))
+}
+
+impl Cache {
+    /* the cache, if there is a daemon keeping it up to date */
+    pub fn active() -> Option<Cache> {
+        let dir = cache_dir()?;
+        if !daemon_running(&dir) {
+            return None;
+        }
+        Some(Cache { dir })
+    }
+
+    fn results_dir(&self) -> PathBuf {
+        self.dir.join("results")
+    }
+
+    fn entry_path(&self, query: &str) -> PathBuf {
+        let mut h = DefaultHasher::new();
+        query.hash(&mut h);
+        self.results_dir().join(format!("{:016x}", h.finish()))
+    }
+
+    pub fn get(&self, query: &str) -> Option<(Vec<String>, Vec<u8>)> {
+        let data = fs::read(self.entry_path(query)).ok()?;
+        let (q, columns, buf) = decode(&data)?;
+        /* guard against hash collisions */
+        if q != query {
+            return None;
+        }
+        Some((columns, buf))
+    }
+
+    /* write via a temporary file, so readers never see a partial entry */
+    fn write_entry(&self, path: &Path, data: &[u8]) -> std::io::Result<()> {
+        let tmp = path.with_extension(format!("tmp{}", process::id()));
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, path)
+    }
+
+    pub fn put(&self, query: &str, columns: &[String], buf: &[u8])
+            -> std::io::Result<()> {
+        fs::create_dir_all(self.results_dir())?;
+        self.write_entry(&self.entry_path(query), &encode(query, columns, buf))
+    }
+
+    /* entries, most recently stored first */
+    fn entries(&self) -> Vec<(PathBuf, std::time::SystemTime)> {
+        let mut entries = match fs::read_dir(self.results_dir()) {
+            Ok(d) => d
+                .filter_map(|e| e.ok())
+                .filter(|e| e.path().extension().is_none())
+                .filter_map(|e| Some((e.path(), e.metadata().ok()?.modified().ok()?)))
+                .collect::<Vec<_>>(),
+            Err(_) => Vec::new(),
+        };
+        entries.sort_by_key(|e| std::cmp::Reverse(e.1));
+        entries
+    }
+
+    /* re-run all cached queries, and prune old entries. Entries keep their
+     * original mtime, so that pruning is by the time of the last search */
+    fn refresh(&self) {
+        let entries = self.entries();
+        let (keep, prune) = entries.split_at(entries.len().min(CACHE_MAX_ENTRIES));
+
+        for (path, _) in prune {
+            let _ = fs::remove_file(path);
+        }
+
+        for (path, mtime) in keep {
+            let query = match fs::read(path).ok().as_deref().and_then(decode) {
+                Some((q, _, _)) => q,
+                None => {
+                    let _ = fs::remove_file(path);
+                    continue;
+                }
+            };
+
+            let res = tracker::query_endpoint_v3(&query)
+                .ok()
+                .and_then(|(columns, buf)| {
+                    self.write_entry(path, &encode(&query, &columns, &buf)).ok()?;
+                    fs::File::options().write(true).open(path).ok()?
+                        .set_modified(*mtime).ok()
+                });
+
+            if res.is_none() {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+
+    fn clear(&self) {
+        let _ = fs::remove_dir_all(self.results_dir());
+    }
+}
+
+/* run the cache daemon: claim the cache, then refresh its contents whenever
+ * the Tracker data changes */
+pub fn run_daemon() -> anyhow::Result<()> {
+    let dir = cache_dir().context("no cache directory")?;
+    let cache = Cache { dir };
+
+    fs::create_dir_all(&cache.dir)
+        .with_context(|| format!("can't create {}", cache.dir.display()))?;
+    cache.clear();
+    fs::write(cache.dir.join("daemon.pid"), process::id().to_string())
+        .context("can't write pid file")?;
+
+    let conn = Connection::new_session()?;
+    let updated = Arc::new(AtomicBool::new(false));
+    let u = updated.clone();
+
+    let rule = MatchRule::new_signal(tracker::ENDPOINT_INTERFACE, "GraphUpdated")
+        .with_sender(tracker::MINER_FILES_SERVICE);
+
+    conn.add_match(rule, move |_: (), _, _| {
+        u.store(true, Ordering::Relaxed);
+        true
+    })?;
+
+    loop {
+        conn.process(Duration::from_secs(60))?;
+
+        if !updated.swap(false, Ordering::Relaxed) {
+            continue;
+        }
+
+        while conn.process(UPDATE_SETTLE_TIME)? {}
+        updated.store(false, Ordering::Relaxed);
+
+        cache.refresh();
+    }
+}
@@ -0,0 +1,136 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Classification of results into broad categories, by mimetype
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Category {
+    Images,
+    Audio,
+    Video,
+    Documents,
+    Archives,
+    Folders,
+    Other,
+}
+
+const DOCUMENT_TYPES: &[&str] = &[
+    "application/pdf",
+    "application/rtf",
+    "application/epub+zip",
+    "application/msword",
+    "application/vnd.oasis.opendocument.",
+    "application/vnd.openxmlformats-officedocument.",
+    "application/vnd.ms-",
+    "application/x-tex",
+];
+
+const ARCHIVE_TYPES: &[&str] = &[
+    "application/zip",
+    "application/x-tar",
+    "application/gzip",
+    "application/x-compressed-tar",
+    "application/x-bzip",
+    "application/x-xz",
+    "application/x-7z-compressed",
+    "application/vnd.rar",
+    "application/x-rar",
+];
+
+impl Category {
+    pub const ALL: [Category; 7] = [
+        Category::Images,
+        Category::Audio,
+        Category::Video,
+        Category::Documents,
+        Category::Archives,
+        Category::Folders,
+        Category::Other,
+    ];
+
+    pub fn from_mimetype(mimetype: &str) -> Category {
+        let (major, _) = mimetype.split_once('/').unwrap_or((mimetype, ""));
+
+        match major {
+            "image" => return Category::Images,
+            "audio" => return Category::Audio,
+            "video" => return Category::Video,
+            "text" => return Category::Documents,
+            _ => (),
+        }
+
+        if mimetype == "inode/directory" {
+            Category::Folders
+        } else if DOCUMENT_TYPES.iter().any(|t| mimetype.starts_with(t)) {
+            Category::Documents
+        } else if ARCHIVE_TYPES.iter().any(|t| mimetype.starts_with(t)) {
+            Category::Archives
+        } else {
+            Category::Other
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Images => "images",
+            Category::Audio => "audio",
+            Category::Video => "video",
+            Category::Documents => "documents",
+            Category::Archives => "archives",
+            Category::Folders => "folders",
+            Category::Other => "other",
+        }
+    }
+
+    /* the name for display, in rows */
+    pub fn label(&self) -> &'static str {
+        match self {
+            Category::Images => "Images",
+            Category::Audio => "Audio",
+            Category::Video => "Video",
+            Category::Documents => "Documents",
+            Category::Archives => "Archives",
+            Category::Folders => "Folders",
+            Category::Other => "Other",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Category> {
+        Category::ALL.iter().copied().find(|c| c.name() == name)
+    }
+
+    /* a category from a type: token, which may be singular ("image") */
+    pub fn from_type_name(name: &str) -> Option<Category> {
+        Category::from_name(name)
+            .or_else(|| Category::from_name(&format!("{}s", name)))
+    }
+
+    /* a SPARQL condition for a mimetype variable being in this category,
+     * matching the classification of from_mimetype */
+    pub fn sparql_cond(&self, var: &str) -> String {
+        let starts = |prefixes: &[&str]| prefixes
+            .iter()
+            .map(|p| format!("STRSTARTS({}, \"{}\")", var, p))
+            .collect::<Vec<_>>()
+            .join(" || ");
+
+        match self {
+            Category::Images => starts(&["image/"]),
+            Category::Audio => starts(&["audio/"]),
+            Category::Video => starts(&["video/"]),
+            Category::Documents => starts(&[&["text/"], DOCUMENT_TYPES].concat()),
+            Category::Archives => starts(ARCHIVE_TYPES),
+            Category::Folders => format!("{} = \"inode/directory\"", var),
+            Category::Other => {
+                let others = Category::ALL
+                    .iter()
+                    .filter(|c| **c != Category::Other)
+                    .map(|c| format!("({})", c.sparql_cond(var)))
+                    .collect::<Vec<_>>()
+                    .join(" || ");
+                format!("!({})", others)
+            }
+        }
+    }
+}
@@ -0,0 +1,37 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Detached children, for work that continues after we've returned our
+ * results to rofi
+ *
+ * We double-fork: the intermediate child starts a new session, forks the
+ * worker and exits, and we reap it straight away. The worker is then
+ * reparented to init (or the session's subreaper), which reaps it in turn,
+ * so no zombies are left behind however long our own process lives. The
+ * worker's stdio is closed, so rofi doesn't wait for it either.
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use anyhow::anyhow;
+use fork::{close_fd, fork, setsid, Fork};
+
+/* run f in a detached process, returning once it has started */
+pub fn detach<F: FnOnce()>(f: F) -> anyhow::Result<()> {
+    match fork() {
+        Err(_) => Err(anyhow!("can't fork")),
+        Ok(Fork::Parent(pid)) => {
+            let mut status = 0;
+            unsafe { libc::waitpid(pid, &mut status, 0) };
+            Ok(())
+        }
+        Ok(Fork::Child) => {
+            let _ = setsid();
+            let _ = close_fd();
+            if let Ok(Fork::Child) = fork() {
+                f();
+                std::process::exit(0);
+            }
+            /* don't run any of our parent's exit handlers */
+            unsafe { libc::_exit(0) }
+        }
+    }
+}
@@ -0,0 +1,272 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Queries through the tracker3 command-line tool
+ *
+ * When the D-Bus endpoint isn't reachable (a broken session bus, or a
+ * sandbox that blocks it), `tracker3 sparql` can still query the miner's
+ * database directly. Its output is meant for people rather than parsing:
+ * rows of comma-separated values, with no quoting. So, we wrap the query to
+ * return each row as a single string, with our own separators, then split
+ * that back into columns, and encode the result as cursor data, as it would
+ * have come from the endpoint.
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use std::env;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use anyhow::{anyhow, Context};
+
+use crate::trace;
+
+/* separators for rows and columns, which we don't expect in values */
+const ROW_SEP: char = '\x1e';
+const COLUMN_SEP: char = '\x1f';
+
+/* the files miner's database */
+fn database_dir() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))?;
+    let dir = dir.join("tracker3").join("files");
+    if dir.is_dir() { Some(dir) } else { None }
+}
+
+/* the range of a SELECT query's projection */
+fn projection(query: &str) -> Option<(usize, usize)> {
+    let start = query.find("SELECT")? + "SELECT".len();
+    let end = start + query[start..].find("WHERE")?;
+    Some((start, end))
+}
+
+/* the variables projected by a SELECT query: plain ?vars, and the ?var of
+ * (expression AS ?var) */
+fn projected_vars(query: &str) -> Option<Vec<String>> {
+    let (start, end) = projection(query)?;
+
+    let mut vars = Vec::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut last_var = None;
+
+    for (i, c) in query[start..end].char_indices() {
+        let i = start + i;
+        match c {
+            '"' => quoted = !quoted,
+            _ if quoted => (),
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                /* the alias is the last variable in the expression */
+                if depth == 0 {
+                    vars.push(last_var.take()?);
+                }
+            }
+            '?' => {
+                let name = query[i + 1..]
+                    .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .next()?;
+                if depth == 0 {
+                    vars.push(name.to_string());
+                } else {
+                    last_var = Some(name.to_string());
+                }
+            }
+            _ => (),
+        }
+    }
+
+    if vars.is_empty() { None } else { Some(vars) }
+}
+
+/* the conditions of a query's ORDER BY clause, which follows its WHERE
+ * block, as (direction, expression) */
+fn order_conditions(query: &str) -> Vec<(&str, &str)> {
+    let tail = match query.rfind('}') {
+        Some(i) => &query[i + 1..],
+        None => return Vec::new(),
+    };
+    let order = match tail.find("ORDER BY") {
+        Some(i) => &tail[i + "ORDER BY".len()..],
+        None => return Vec::new(),
+    };
+    let end = ["OFFSET", "LIMIT"]
+        .iter()
+        .filter_map(|k| order.find(k))
+        .min()
+        .unwrap_or(order.len());
+
+    /* split on whitespace outside of brackets and literals */
+    let mut conds = Vec::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut start = None;
+
+    for (i, c) in order[..end].char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            _ if quoted => (),
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            c if c.is_whitespace() && depth == 0 => {
+                if let Some(s) = start.take() {
+                    conds.push(&order[s..i]);
+                }
+                continue;
+            }
+            _ => (),
+        }
+        start.get_or_insert(i);
+    }
+    if let Some(s) = start {
+        conds.push(order[s..end].trim_end());
+    }
+
+    conds
+        .into_iter()
+        .map(|c| ["ASC", "DESC"]
+             .iter()
+             .find_map(|d| Some((*d, c.strip_prefix(d)?
+                                 .strip_prefix('(')?
+                                 .strip_suffix(')')?)))
+             .unwrap_or(("ASC", c)))
+        .collect()
+}
+
+/* wrap a query to return each row as a single separated string. The order
+ * of a subquery's results isn't kept, so its ordering expressions are
+ * projected, and the rows ordered by those */
+fn row_query(query: &str, vars: &[String]) -> String {
+    let cols = vars
+        .iter()
+        .map(|v| format!(r#"COALESCE(STR(?{}), "")"#, v))
+        .collect::<Vec<_>>()
+        .join(&format!(r#", "{}", "#, COLUMN_SEP));
+
+    let order = order_conditions(query);
+    let inner = match projection(query) {
+        Some((_, end)) if !order.is_empty() => {
+            let keys = order
+                .iter()
+                .enumerate()
+                .map(|(i, (_, e))| format!("({} AS ?_order{})", e, i))
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!("{} {}\n{}", query[..end].trim_end(), keys, &query[end..])
+        }
+        _ => query.to_string(),
+    };
+
+    let mut q = format!(r#"SELECT (CONCAT("{}", {}) AS ?row) WHERE {{ {{ {} }} }}"#,
+                        ROW_SEP, cols, inner);
+    if !order.is_empty() {
+        let keys = order
+            .iter()
+            .enumerate()
+            .map(|(i, (d, _))| format!("{}(?_order{})", d, i))
+            .collect::<Vec<_>>()
+            .join(" ");
+        q += &format!(" ORDER BY {}", keys);
+    }
+    q
+}
+
+/* encode rows as cursor data, as parsed by tracker::parse_row */
+fn encode_rows(rows: &[Vec<&str>]) -> Vec<u8> {
+    let mut buf = Vec::new();
+
+    for row in rows {
+        let n = row.len() as u32;
+        buf.extend(n.to_ne_bytes());
+        /* all values are strings */
+        for _ in row {
+            buf.extend(1u32.to_ne_bytes());
+        }
+        let mut offset = 0;
+        for v in row {
+            offset += v.len() as u32;
+            buf.extend(offset.to_ne_bytes());
+            offset += 1;
+        }
+        for v in row {
+            buf.extend(v.as_bytes());
+            buf.push(0);
+        }
+    }
+
+    buf
+}
+
+/* run a query, returning the column names and cursor data */
+pub fn query(query: &str) -> anyhow::Result<(Vec<String>, Vec<u8>)> {
+    let db = database_dir().context("no tracker3 database")?;
+    let vars = projected_vars(query).context("can't find query columns")?;
+    let wrapped = row_query(query, &vars);
+    trace::query("tracker3", &wrapped);
+
+    let output = Command::new("tracker3")
+        .arg("sparql")
+        .arg("--database")
+        .arg(&db)
+        .arg("--query")
+        .arg(&wrapped)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .context("can't run tracker3")?;
+
+    if !output.status.success() {
+        return Err(anyhow!("tracker3 sparql failed: {}", output.status));
+    }
+
+    let out = String::from_utf8_lossy(&output.stdout);
+
+    /* skip the header, then strip each row's line ending, and the next
+     * row's indent */
+    let rows = out
+        .split(ROW_SEP)
+        .skip(1)
+        .map(|r| {
+            let r = r.strip_suffix("\n  ")
+                .or_else(|| r.strip_suffix('\n'))
+                .unwrap_or(r);
+            r.split(COLUMN_SEP).collect::<Vec<_>>()
+        })
+        .filter(|r| r.len() == vars.len())
+        .collect::<Vec<_>>();
+
+    Ok((vars, encode_rows(&rows)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn order_clause() {
+        let q = "SELECT ?s ?n WHERE { ?s a ?n FILTER (?n = \"} ORDER BY ?x\") }\n\
+                 ORDER BY DESC(IF(CONTAINS(?n, \"a b)\"), 1, 0) + 2) ?n ASC(?s)\n\
+                 LIMIT 10";
+        assert_eq!(order_conditions(q), [
+            ("DESC", r#"IF(CONTAINS(?n, "a b)"), 1, 0) + 2"#),
+            ("ASC", "?n"),
+            ("ASC", "?s"),
+        ]);
+        assert!(order_conditions("SELECT ?s WHERE { ?s a ?t }").is_empty());
+    }
+
+    #[test]
+    fn row_query_keeps_order() {
+        let q = "SELECT ?s (?t AS ?u)\nWHERE {\n?s a ?t\n}\nORDER BY DESC(fts:rank(?s))\nLIMIT 5";
+        let vars = projected_vars(q).unwrap();
+        assert_eq!(row_query(q, &vars), concat!(
+            "SELECT (CONCAT(\"\x1e\", COALESCE(STR(?s), \"\"), \"\x1f\", ",
+            r#"COALESCE(STR(?u), "")) AS ?row) WHERE { { "#,
+            "SELECT ?s (?t AS ?u) (fts:rank(?s) AS ?_order0)\n",
+            "WHERE {\n?s a ?t\n}\nORDER BY DESC(fts:rank(?s))\nLIMIT 5 } } ",
+            "ORDER BY DESC(?_order0)"));
+    }
+}
@@ -0,0 +1,163 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Clipboard access, through whichever mechanism suits the session
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::process::{Command, Stdio};
+use anyhow::{anyhow, Context};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    WlCopy,
+    Xclip,
+    Xsel,
+    /* the OSC 52 terminal escape sequence, for terminal sessions (possibly
+     * remote) without a display server */
+    Osc52,
+}
+
+impl Backend {
+    const ALL: [Backend; 4] =
+        [Backend::WlCopy, Backend::Xclip, Backend::Xsel, Backend::Osc52];
+
+    pub fn from_name(name: &str) -> Option<Backend> {
+        Backend::ALL.iter().copied().find(|b| b.name() == name)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::WlCopy => "wl-copy",
+            Backend::Xclip => "xclip",
+            Backend::Xsel => "xsel",
+            Backend::Osc52 => "osc52",
+        }
+    }
+
+    /* whether the backend's command is installed, or we have a terminal */
+    pub fn available(&self) -> bool {
+        match self.command() {
+            Some(cmd) => env::var_os("PATH").is_some_and(|path| env::split_paths(&path)
+                .any(|d| d.join(cmd.get_program()).is_file())),
+            None => OpenOptions::new().write(true).open("/dev/tty").is_ok(),
+        }
+    }
+
+    fn command(&self) -> Option<Command> {
+        let mut cmd = match self {
+            Backend::WlCopy => Command::new("wl-copy"),
+            Backend::Xclip => Command::new("xclip"),
+            Backend::Xsel => Command::new("xsel"),
+            Backend::Osc52 => return None,
+        };
+        match self {
+            Backend::Xclip => { cmd.args(["-selection", "clipboard"]); }
+            Backend::Xsel => { cmd.args(["--clipboard", "--input"]); }
+            _ => (),
+        }
+        Some(cmd)
+    }
+
+    /* copy text, returning Ok(false) if the backend isn't available */
+    fn copy(&self, text: &str) -> anyhow::Result<bool> {
+        let mut cmd = match self.command() {
+            Some(c) => c,
+            None => return osc52_copy(text),
+        };
+
+        let child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+
+        let mut child = match child {
+            Ok(c) => c,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e)
+                .with_context(|| format!("can't run {:?}", cmd.get_program())),
+        };
+
+        child.stdin
+            .take()
+            .context("no stdin")?
+            .write_all(text.as_bytes())?;
+
+        let status = child.wait()?;
+        if !status.success() {
+            return Err(anyhow!("{:?} failed: {}", cmd.get_program(), status));
+        }
+
+        Ok(true)
+    }
+}
+
+/* backends to try, in order, for the current session */
+fn session_backends() -> Vec<Backend> {
+    let mut backends = Vec::new();
+
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        backends.push(Backend::WlCopy);
+    }
+    if env::var_os("DISPLAY").is_some() {
+        backends.push(Backend::Xclip);
+        backends.push(Backend::Xsel);
+    }
+    backends.push(Backend::Osc52);
+
+    backends
+}
+
+const BASE64_CHARS: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(data: &[u8]) -> String {
+    let mut s = String::new();
+
+    for chunk in data.chunks(3) {
+        let b = [chunk[0],
+                 chunk.get(1).copied().unwrap_or(0),
+                 chunk.get(2).copied().unwrap_or(0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                s.push(BASE64_CHARS[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                s.push('=');
+            }
+        }
+    }
+
+    s
+}
+
+fn osc52_copy(text: &str) -> anyhow::Result<bool> {
+    let mut tty = match OpenOptions::new().write(true).open("/dev/tty") {
+        Ok(t) => t,
+        Err(_) => return Ok(false),
+    };
+
+    write!(tty, "\x1b]52;c;{}\x07", base64(text.as_bytes()))?;
+    Ok(true)
+}
+
+/* copy text to the clipboard, with a specific backend, or the first
+ * available backend for the session */
+pub fn copy(text: &str, backend: Option<Backend>) -> anyhow::Result<()> {
+    let backends = match backend {
+        Some(b) => vec![b],
+        None => session_backends(),
+    };
+
+    for b in backends {
+        if b.copy(text)? {
+            return Ok(());
+        }
+    }
+
+    Err(anyhow!("no clipboard mechanism available"))
+}
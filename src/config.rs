@@ -0,0 +1,920 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Configuration file handling
+ *
+ * The config file is a small subset of TOML: `key = value` pairs, with
+ * optional `[section]` headers. Values may be strings, integers, booleans,
+ * lists (`[ ... ]`) or inline tables (`{ key = value, ... }`).
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+use anyhow::{anyhow, Context};
+
+use crate::action::{self, Action};
+use crate::category::Category;
+use crate::clipboard;
+use crate::editor;
+use crate::ignore;
+use crate::row::{valid_color, Flag, State, Style};
+use crate::sparql;
+use crate::stem;
+use crate::tracker;
+
+use nom::IResult;
+use nom::branch::alt;
+use nom::bytes::complete::{escaped_transform, is_not, tag, take_while, take_while1};
+use nom::character::complete::{char, digit1, multispace1, not_line_ending, space0};
+use nom::combinator::{map, map_res, opt, recognize, value};
+use nom::multi::many0;
+use nom::sequence::{delimited, pair, preceded, separated_pair, terminated, tuple};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Str(String),
+    Int(i64),
+    Bool(bool),
+    List(Vec<Value>),
+    Table(Vec<Entry>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub key: String,
+    pub value: Value,
+    pub line: usize,
+}
+
+fn comment(i: &str) -> IResult<&str, &str> {
+    preceded(char('#'), not_line_ending)(i)
+}
+
+/* whitespace, including newlines and comments */
+fn ws(i: &str) -> IResult<&str, &str> {
+    recognize(many0(alt((multispace1, comment))))(i)
+}
+
+fn basic_string(i: &str) -> IResult<&str, String> {
+    let escape = alt((
+        value("\\", tag("\\")),
+        value("\"", tag("\"")),
+        value("\n", tag("n")),
+        value("\t", tag("t")),
+    ));
+    map(delimited(char('"'),
+                  opt(escaped_transform(is_not("\\\"\n"), '\\', escape)),
+                  char('"')),
+        Option::unwrap_or_default)(i)
+}
+
+fn literal_string(i: &str) -> IResult<&str, String> {
+    map(delimited(char('\''), take_while(|c| c != '\'' && c != '\n'), char('\'')),
+        String::from)(i)
+}
+
+fn key(i: &str) -> IResult<&str, String> {
+    alt((
+        map(take_while1(|c: char| c.is_alphanumeric() || c == '_' || c == '-'),
+            String::from),
+        basic_string,
+        literal_string,
+    ))(i)
+}
+
+fn integer(i: &str) -> IResult<&str, i64> {
+    map_res(recognize(pair(opt(char('-')), digit1)), str::parse)(i)
+}
+
+fn boolean(i: &str) -> IResult<&str, bool> {
+    alt((value(true, tag("true")), value(false, tag("false"))))(i)
+}
+
+fn list(i: &str) -> IResult<&str, Vec<Value>> {
+    delimited(
+        char('['),
+        many0(terminated(preceded(ws, config_value),
+                         preceded(ws, opt(char(','))))),
+        preceded(ws, char(']')),
+    )(i)
+}
+
+fn table_entry(i: &str) -> IResult<&str, (String, Value)> {
+    separated_pair(key, tuple((space0, char('='), space0)), config_value)(i)
+}
+
+fn table(i: &str) -> IResult<&str, Vec<(String, Value)>> {
+    delimited(
+        char('{'),
+        many0(terminated(preceded(ws, table_entry),
+                         preceded(ws, opt(char(','))))),
+        preceded(ws, char('}')),
+    )(i)
+}
+
+fn config_value(i: &str) -> IResult<&str, Value> {
+    alt((
+        map(basic_string, Value::Str),
+        map(literal_string, Value::Str),
+        map(boolean, Value::Bool),
+        map(integer, Value::Int),
+        map(list, Value::List),
+        map(table, table_value),
+    ))(i)
+}
+
+/* end of a statement: optional trailing comment, then newline or EOF */
+fn statement_end(i: &str) -> IResult<&str, ()> {
+    let (i, _) = space0(i)?;
+    let (i, _) = opt(comment)(i)?;
+    if i.is_empty() {
+        return Ok((i, ()));
+    }
+    value((), alt((tag("\r\n"), tag("\n"))))(i)
+}
+
+/* inline table entries; line numbers are filled in from the parent entry */
+fn table_value(entries: Vec<(String, Value)>) -> Value {
+    Value::Table(entries
+        .into_iter()
+        .map(|(key, value)| Entry { key, value, line: 0 })
+        .collect())
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Str(_) => "string",
+            Value::Int(_) => "integer",
+            Value::Bool(_) => "boolean",
+            Value::List(_) => "list",
+            Value::Table(_) => "table",
+        }
+    }
+}
+
+impl Entry {
+    fn type_error(&self, expected: &str) -> anyhow::Error {
+        anyhow!("line {}: '{}' should be a {}, not a {}",
+                self.line, self.key, expected, self.value.type_name())
+    }
+
+    pub fn as_str(&self) -> anyhow::Result<&str> {
+        match &self.value {
+            Value::Str(s) => Ok(s),
+            _ => Err(self.type_error("string")),
+        }
+    }
+
+    pub fn as_bool(&self) -> anyhow::Result<bool> {
+        match self.value {
+            Value::Bool(b) => Ok(b),
+            _ => Err(self.type_error("boolean")),
+        }
+    }
+
+    pub fn as_int(&self) -> anyhow::Result<i64> {
+        match self.value {
+            Value::Int(i) => Ok(i),
+            _ => Err(self.type_error("integer")),
+        }
+    }
+
+    pub fn as_str_list(&self) -> anyhow::Result<Vec<String>> {
+        match &self.value {
+            Value::List(l) => l.iter().map(|v| match v {
+                Value::Str(s) => Ok(s.clone()),
+                _ => Err(self.type_error("list of strings")),
+            }).collect(),
+            _ => Err(self.type_error("list of strings")),
+        }
+    }
+
+    /* table members, with the line numbers of inline tables filled in */
+    pub fn as_table(&self) -> anyhow::Result<Vec<Entry>> {
+        match &self.value {
+            Value::Table(t) => Ok(t.iter().map(|e| Entry {
+                line: if e.line == 0 { self.line } else { e.line },
+                ..e.clone()
+            }).collect()),
+            _ => Err(self.type_error("table")),
+        }
+    }
+}
+
+/* parse config text into a list of top-level entries. Sections are
+ * represented as a table-valued entry */
+pub fn parse(text: &str) -> anyhow::Result<Vec<Entry>> {
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut section: Option<usize> = None;
+    let mut input = text;
+
+    loop {
+        let (i, _) = ws(input).map_err(|_| anyhow!("invalid input"))?;
+        input = i;
+        if input.is_empty() {
+            break;
+        }
+
+        let line = text[..text.len() - input.len()].matches('\n').count() + 1;
+
+        let header: IResult<&str, String> = terminated(
+            delimited(char('['), key, char(']')),
+            statement_end)(input);
+
+        if let Ok((i, name)) = header {
+            entries.push(Entry { key: name, value: Value::Table(Vec::new()), line });
+            section = Some(entries.len() - 1);
+            input = i;
+            continue;
+        }
+
+        let (i, (key, value)) = terminated(table_entry, statement_end)(input)
+            .map_err(|_| anyhow!("line {}: invalid syntax", line))?;
+        input = i;
+
+        let entry = Entry { key, value, line };
+
+        match section {
+            Some(idx) => {
+                if let Value::Table(t) = &mut entries[idx].value {
+                    t.push(entry);
+                }
+            }
+            None => entries.push(entry),
+        }
+    }
+
+    Ok(entries)
+}
+
+/* a table keyed by mimetype, mimetype wildcard, or category name */
+fn parse_mimetype_map(entry: &Entry) -> anyhow::Result<Vec<(String, String)>> {
+    entry.as_table()?
+        .iter()
+        .map(|e| {
+            if !e.key.contains('/') && Category::from_name(&e.key).is_none() {
+                return Err(anyhow!("line {}: invalid file type '{}'",
+                                   e.line, e.key));
+            }
+            Ok((e.key.clone(), e.as_str()?.to_string()))
+        })
+        .collect()
+}
+
+/* lookup a mimetype in a mimetype map: from an exact match, then a
+ * wildcard, then its category */
+fn lookup_mimetype<'a>(map: &'a [(String, String)], mimetype: &str)
+        -> Option<&'a str> {
+    let find = |key: &str| map
+        .iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.as_str());
+
+    let wildcard = mimetype
+        .split_once('/')
+        .map(|(major, _)| format!("{}/*", major));
+
+    find(mimetype)
+        .or_else(|| wildcard.and_then(|w| find(&w)))
+        .or_else(|| find(Category::from_mimetype(mimetype).name()))
+}
+
+fn parse_style(entry: &Entry) -> anyhow::Result<Style> {
+    let mut style = Style::default();
+
+    for e in entry.as_table()? {
+        match e.key.as_str() {
+            "flag" => {
+                let f = e.as_str()?;
+                style.flag = Some(Flag::from_name(f)
+                    .ok_or_else(|| anyhow!("line {}: unknown flag '{}'", e.line, f))?);
+            }
+            "color" => {
+                let c = e.as_str()?;
+                if !valid_color(c) {
+                    return Err(anyhow!("line {}: invalid color '{}'", e.line, c));
+                }
+                style.color = Some(c.to_string());
+            }
+            _ => return Err(anyhow!("line {}: unknown style property '{}'",
+                                    e.line, e.key)),
+        }
+    }
+
+    Ok(style)
+}
+
+/* case sensitivity of filename and path matches */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum CaseMode {
+    /* case-insensitive, unless the text includes an uppercase character */
+    #[default]
+    Smart,
+    Sensitive,
+    Insensitive,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ExportFormat {
+    #[default]
+    Paths,
+    Uris,
+}
+
+/* expand a leading ~/ in a path to the home directory */
+pub fn expand_path(s: &str) -> PathBuf {
+    match (s.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(s),
+    }
+}
+
+/* commands are either a string of whitespace-separated arguments, or a
+ * list of arguments */
+fn parse_command(entry: &Entry) -> anyhow::Result<Vec<String>> {
+    let argv = match entry.as_str() {
+        Ok(s) => s.split_whitespace().map(str::to_string).collect(),
+        Err(_) => entry.as_str_list()?,
+    };
+
+    if argv.is_empty() {
+        return Err(anyhow!("line {}: empty command '{}'", entry.line, entry.key));
+    }
+
+    Ok(argv)
+}
+
+#[derive(Debug, Default)]
+pub struct Config {
+    /* properties that search terms are matched against, with their
+     * relative ranking weights. Empty means all FTS properties, unranked */
+    pub fts_properties: Vec<(String, i64)>,
+    /* mimetypes to exclude from results */
+    pub exclude_mimetypes: Vec<String>,
+    /* maximum number of results to show from each category */
+    pub max_per_category: Vec<(Category, usize)>,
+    /* graphs to restrict results to; empty for all graphs */
+    pub graphs: Vec<String>,
+    /* services to probe for contacts and events endpoints, before those
+     * found on the session bus */
+    pub endpoints: Vec<String>,
+    /* ranking score added for favorite (starred) results */
+    pub favorite_boost: i64,
+    pub case: CaseMode,
+    /* language for query-side stemming, or None to match words as typed */
+    pub stem_language: Option<String>,
+    /* gitignore-style patterns for files to leave out of results */
+    pub ignore: Vec<ignore::Pattern>,
+    /* also read patterns from the global ignore file, and the .gitignore
+     * and .ignore files in each of ignore_roots */
+    pub ignore_files: bool,
+    pub ignore_roots: Vec<PathBuf>,
+    /* include hidden files, and files in hidden directories */
+    pub show_hidden: bool,
+    /* include results from unavailable (eg., unmounted) volumes */
+    pub show_offline: bool,
+    /* preferences for file extensions (without the '.', in lowercase):
+     * results with higher values are listed first */
+    pub extensions: Vec<(String, i64)>,
+    /* text badges to prefix rows with, by mimetype, mimetype wildcard (a
+     * major type with a "*" subtype) or category name */
+    pub badges: Vec<(String, String)>,
+    /* icon names (or paths) for rows, overriding the standard names, keyed
+     * as for badges */
+    pub icons: Vec<(String, String)>,
+    /* row styles to apply to results in each state */
+    pub styles: Vec<(State, Style)>,
+    /* offer spelling corrections for searches with no results, from the
+     * names of opened files and previous searches, which are recorded */
+    pub spelling: bool,
+    /* restrict results to a type of file hinted at by words in the query,
+     * such as "photo" or "song" */
+    pub auto_type: bool,
+    /* have rofi match and sort on plain row text, showing decorations
+     * with the display option */
+    pub rofi_sort: bool,
+    /* offer rows filtering results to each category, with their counts */
+    pub category_rows: bool,
+    /* with the cache daemon, list results from a fast query first, then
+     * fill in the details from a full query */
+    pub progressive: bool,
+    /* offer to repeat the last query of the session, when rofi starts */
+    pub restore_query: bool,
+    /* list recently modified files when rofi starts, grouped by when they
+     * were modified */
+    pub recent_files: bool,
+    /* number the first rows, and open the Nth result for a "!N" suffix */
+    pub quick_select: bool,
+    /* user-defined commands, as argument templates */
+    pub commands: Vec<(String, Vec<String>)>,
+    /* editor to open text matches in, at the matching line */
+    pub editor: Option<String>,
+    /* editor command templates, adding to (or overriding) the built-in
+     * ones */
+    pub editors: Vec<(String, Vec<String>)>,
+    /* actions bound to rofi's kb-custom-N keys, by N */
+    pub keys: HashMap<u32, Action>,
+    /* clipboard mechanism, or None to detect from the session */
+    pub clipboard: Option<clipboard::Backend>,
+    /* report a file's handler by notification if it hasn't finished
+     * starting after this long */
+    pub open_timeout: Option<Duration>,
+    /* a path in the current project, for :here searches */
+    pub project: Option<PathBuf>,
+    /* file for the export action, or None for the default */
+    pub export_file: Option<PathBuf>,
+    pub export_format: ExportFormat,
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        let dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+        Some(dir.join("tracker-rofi").join("config.toml"))
+    }
+
+    /* whether to record opened files in the history. Only spelling and the
+     * recent style read them, so we don't keep a record otherwise */
+    pub fn records_opens(&self) -> bool {
+        self.spelling || self.styles.iter().any(|(s, _)| *s == State::Recent)
+    }
+
+    pub fn badge(&self, mimetype: &str) -> Option<&str> {
+        lookup_mimetype(&self.badges, mimetype)
+    }
+
+    /* the icon for a mimetype, as an icon name or path: from the configured
+     * icons, else the standard icon name for the mimetype */
+    pub fn icon(&self, mimetype: &str) -> Option<String> {
+        if let Some(i) = lookup_mimetype(&self.icons, mimetype) {
+            return Some(i.to_string());
+        }
+        if mimetype.is_empty() {
+            None
+        } else {
+            Some(mimetype.replace('/', "-"))
+        }
+    }
+
+    pub fn export_file(&self) -> PathBuf {
+        if let Some(f) = &self.export_file {
+            return f.clone();
+        }
+        let dir = env::var_os("XDG_RUNTIME_DIR")
+            .map_or_else(env::temp_dir, PathBuf::from);
+        dir.join("tracker-rofi-results")
+    }
+
+    pub fn load() -> anyhow::Result<Config> {
+        let path = match Config::path() {
+            Some(p) => p,
+            None => return Ok(Config::default()),
+        };
+
+        let text = match fs::read_to_string(&path) {
+            Ok(t) => t,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound =>
+                return Ok(Config::default()),
+            Err(e) => return Err(e)
+                .with_context(|| format!("can't read {}", path.display())),
+        };
+
+        Config::parse(&text)
+            .with_context(|| format!("invalid config {}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> anyhow::Result<Config> {
+        let mut config = Config::default();
+        let entries = parse(text)?;
+
+        /* commands first, as keys may refer to them */
+        let (commands, others): (Vec<_>, Vec<_>) = entries
+            .iter()
+            .partition(|e| e.key == "commands");
+
+        for entry in commands.iter().chain(others.iter()) {
+            config.apply(entry)?;
+        }
+
+        Ok(config)
+    }
+
+    /* check config text, returning all of the problems found, rather than
+     * stopping at the first */
+    pub fn check(text: &str) -> Vec<String> {
+        let mut config = Config::default();
+        let mut problems = Vec::new();
+
+        let entries = match parse(text) {
+            Ok(e) => e,
+            Err(e) => return vec![e.to_string()],
+        };
+
+        let (commands, others): (Vec<_>, Vec<_>) = entries
+            .iter()
+            .partition(|e| e.key == "commands");
+
+        for entry in commands.iter().chain(others.iter()) {
+            match config.apply(entry) {
+                Ok(true) => (),
+                Ok(false) => problems.push(format!("line {}: unknown key '{}'",
+                                                   entry.line, entry.key)),
+                Err(e) => problems.push(e.to_string()),
+            }
+        }
+
+        /* commands with placeholders we don't know are likely typos */
+        let templates = |key: &str| entries
+            .iter()
+            .filter(|e| e.key == key)
+            .filter_map(|e| e.as_table().ok())
+            .flatten()
+            .collect::<Vec<_>>();
+
+        let all_commands = templates("commands")
+            .into_iter()
+            .map(|e| (e, &[][..]))
+            .chain(templates("editors")
+                   .into_iter()
+                   .map(|e| (e, &[editor::LINE_PLACEHOLDER][..])));
+
+        for (entry, extra) in all_commands {
+            for arg in parse_command(&entry).unwrap_or_default() {
+                if !action::valid_template(&arg, extra) {
+                    problems.push(format!("line {}: unknown placeholder in '{}'",
+                                          entry.line, arg));
+                }
+            }
+        }
+
+        let line = |key: &str| entries
+            .iter()
+            .find(|e| e.key == key)
+            .map_or(0, |e| e.line);
+
+        if let Some(e) = &config.editor {
+            if editor::template(&config).is_none() {
+                problems.push(format!("line {}: unknown editor '{}'",
+                                      line("editor"), e));
+            }
+        }
+
+        problems.extend(ignore::check(&config));
+
+        if let Err(e) = tracker::ping(tracker::MINER_FILES_SERVICE) {
+            problems.push(format!("can't reach Tracker: {}", e));
+        }
+
+        for service in &config.endpoints {
+            if tracker::ping(service).is_err() {
+                problems.push(format!("line {}: can't reach endpoint '{}'",
+                                      line("endpoints"), service));
+            }
+        }
+
+        if let Some(b) = config.clipboard {
+            if !b.available() {
+                problems.push(format!("line {}: clipboard '{}' isn't available",
+                                      line("clipboard"), b.name()));
+            }
+        }
+
+        problems
+    }
+
+    /* apply a top-level entry; unknown keys are ignored, returning false */
+    fn apply(&mut self, entry: &Entry) -> anyhow::Result<bool> {
+        match entry.key.as_str() {
+            "fts_properties" => {
+                self.fts_properties = entry.as_table()?
+                    .iter()
+                    .map(|e| {
+                        if !sparql::valid_prefixed_name(&e.key) {
+                            return Err(anyhow!("line {}: invalid property '{}'",
+                                               e.line, e.key));
+                        }
+                        Ok((e.key.clone(), e.as_int()?))
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+            }
+            "exclude_mimetypes" => {
+                self.exclude_mimetypes = entry.as_str_list()?;
+            }
+            "max_per_category" => {
+                self.max_per_category = entry.as_table()?
+                    .iter()
+                    .map(|e| {
+                        let cat = Category::from_name(&e.key)
+                            .ok_or_else(|| anyhow!("line {}: unknown category '{}'",
+                                                   e.line, e.key))?;
+                        let max = usize::try_from(e.as_int()?)
+                            .map_err(|_| anyhow!("line {}: invalid quota for '{}'",
+                                                 e.line, e.key))?;
+                        Ok((cat, max))
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+            }
+            "clipboard" => {
+                let name = entry.as_str()?;
+                self.clipboard = match name {
+                    "auto" => None,
+                    _ => Some(clipboard::Backend::from_name(name)
+                        .ok_or_else(|| anyhow!("line {}: unknown clipboard '{}'",
+                                               entry.line, name))?),
+                };
+            }
+            "open_timeout" => {
+                let secs = u64::try_from(entry.as_int()?)
+                    .map_err(|_| anyhow!("line {}: invalid open timeout", entry.line))?;
+                self.open_timeout = Some(secs)
+                    .filter(|s| *s > 0)
+                    .map(Duration::from_secs);
+            }
+            "project" => {
+                self.project = Some(expand_path(entry.as_str()?));
+            }
+            "export_file" => {
+                self.export_file = Some(expand_path(entry.as_str()?));
+            }
+            "export_format" => {
+                self.export_format = match entry.as_str()? {
+                    "paths" => ExportFormat::Paths,
+                    "uris" => ExportFormat::Uris,
+                    f => return Err(anyhow!("line {}: unknown export format '{}'",
+                                            entry.line, f)),
+                };
+            }
+            "commands" => {
+                self.commands = entry.as_table()?
+                    .iter()
+                    .map(|e| Ok((e.key.clone(), parse_command(e)?)))
+                    .collect::<anyhow::Result<_>>()?;
+            }
+            "editor" => {
+                self.editor = Some(entry.as_str()?.to_string());
+            }
+            "editors" => {
+                self.editors = entry.as_table()?
+                    .iter()
+                    .map(|e| Ok((e.key.clone(), parse_command(e)?)))
+                    .collect::<anyhow::Result<_>>()?;
+            }
+            "keys" => {
+                self.keys = entry.as_table()?
+                    .iter()
+                    .map(|e| {
+                        let n = e.key.strip_prefix("custom-")
+                            .and_then(|n| n.parse().ok())
+                            .filter(|n| (1..=action::CUSTOM_KEYS).contains(n))
+                            .ok_or_else(|| anyhow!("line {}: invalid key '{}'",
+                                                   e.line, e.key))?;
+                        let name = e.as_str()?;
+                        let action = Action::from_name(name, &self.commands)
+                            .ok_or_else(|| anyhow!("line {}: unknown action '{}'",
+                                                   e.line, name))?;
+                        Ok((n, action))
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+            }
+            "spelling" => {
+                self.spelling = entry.as_bool()?;
+            }
+            "auto_type" => {
+                self.auto_type = entry.as_bool()?;
+            }
+            "rofi_sort" => {
+                self.rofi_sort = entry.as_bool()?;
+            }
+            "category_rows" => {
+                self.category_rows = entry.as_bool()?;
+            }
+            "progressive" => {
+                self.progressive = entry.as_bool()?;
+            }
+            "recent_files" => {
+                self.recent_files = entry.as_bool()?;
+            }
+            "restore_query" => {
+                self.restore_query = entry.as_bool()?;
+            }
+            "quick_select" => {
+                self.quick_select = entry.as_bool()?;
+            }
+            "graphs" => {
+                self.graphs = entry.as_str_list()?
+                    .iter()
+                    .map(|g| sparql::graph_name(g).ok_or_else(||
+                        anyhow!("line {}: invalid graph '{}'", entry.line, g)))
+                    .collect::<anyhow::Result<_>>()?;
+            }
+            "endpoints" => {
+                self.endpoints = entry.as_str_list()?;
+            }
+            "favorite_boost" => {
+                self.favorite_boost = entry.as_int()?;
+            }
+            "stem_language" => {
+                let lang = entry.as_str()?;
+                if !stem::valid_language(lang) {
+                    return Err(anyhow!("line {}: unsupported stemming language '{}'",
+                                       entry.line, lang));
+                }
+                self.stem_language = Some(lang.to_string());
+            }
+            "case" => {
+                self.case = match entry.as_str()? {
+                    "smart" => CaseMode::Smart,
+                    "sensitive" => CaseMode::Sensitive,
+                    "insensitive" => CaseMode::Insensitive,
+                    c => return Err(anyhow!("line {}: unknown case mode '{}'",
+                                            entry.line, c)),
+                };
+            }
+            "ignore" => {
+                self.ignore = entry.as_str_list()?
+                    .iter()
+                    .filter_map(|p| ignore::Pattern::parse(p)
+                        .map_err(|e| anyhow!("line {}: {}", entry.line, e))
+                        .transpose())
+                    .collect::<anyhow::Result<_>>()?;
+            }
+            "ignore_files" => {
+                self.ignore_files = entry.as_bool()?;
+            }
+            "ignore_roots" => {
+                self.ignore_roots = entry.as_str_list()?
+                    .iter()
+                    .map(|r| expand_path(r))
+                    .collect();
+            }
+            "show_hidden" => {
+                self.show_hidden = entry.as_bool()?;
+            }
+            "show_offline" => {
+                self.show_offline = entry.as_bool()?;
+            }
+            "extensions" => {
+                self.extensions = entry.as_table()?
+                    .iter()
+                    .map(|e| {
+                        let ext = e.key.trim_start_matches('.').to_lowercase();
+                        if ext.is_empty() || ext.contains('/') {
+                            return Err(anyhow!("line {}: invalid extension '{}'",
+                                               e.line, e.key));
+                        }
+                        Ok((ext, e.as_int()?))
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+            }
+            "badges" => self.badges = parse_mimetype_map(entry)?,
+            "icons" => self.icons = parse_mimetype_map(entry)?,
+            "styles" => {
+                self.styles = entry.as_table()?
+                    .iter()
+                    .map(|e| {
+                        let state = State::from_name(&e.key)
+                            .ok_or_else(|| anyhow!("line {}: unknown state '{}'",
+                                                   e.line, e.key))?;
+                        Ok((state, parse_style(e)?))
+                    })
+                    .collect::<anyhow::Result<_>>()?;
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(key: &str, value: Value, line: usize) -> Entry {
+        Entry { key: key.to_string(), value, line }
+    }
+
+    fn string(s: &str) -> Value {
+        Value::Str(s.to_string())
+    }
+
+    #[test]
+    fn values() {
+        let text = r#"
+# a comment
+a = "string with \"quotes\", \\ and \n"
+b = 'literal \n'   # trailing comment
+c = -42
+d = true
+e = [ "x", 'y',
+      "z", ]
+f = { g = 1, "h.i" = false }
+empty = ""
+"#;
+        assert_eq!(parse(text).unwrap(), [
+            entry("a", string("string with \"quotes\", \\ and \n"), 3),
+            entry("b", string("literal \\n"), 4),
+            entry("c", Value::Int(-42), 5),
+            entry("d", Value::Bool(true), 6),
+            entry("e", Value::List(vec![string("x"), string("y"), string("z")]), 7),
+            entry("f", Value::Table(vec![
+                entry("g", Value::Int(1), 0),
+                entry("h.i", Value::Bool(false), 0),
+            ]), 9),
+            entry("empty", string(""), 10),
+        ]);
+    }
+
+    #[test]
+    fn sections() {
+        let text = "top = 1\r\n[keys]\ncustom-1 = \"select\"\n\n[styles]\n";
+        assert_eq!(parse(text).unwrap(), [
+            entry("top", Value::Int(1), 1),
+            entry("keys", Value::Table(vec![entry("custom-1", string("select"), 3)]), 2),
+            entry("styles", Value::Table(Vec::new()), 5),
+        ]);
+    }
+
+    #[test]
+    fn inline_table_lines() {
+        let entries = parse("\n\nx = { a = 1 }").unwrap();
+        assert_eq!(entries[0].as_table().unwrap()[0].line, 3);
+    }
+
+    #[test]
+    fn malformed() {
+        let cases = [
+            ("a = ", 1),
+            ("a = \"unterminated", 1),
+            ("a = 'multi\nline'", 1),
+            ("ok = 1\nb = [1, 2", 2),
+            ("ok = 1\nc = { d = 1", 2),
+            ("e = 1 f = 2", 1),
+            ("[section\n", 1),
+            ("\n\n= 1", 3),
+            ("g = \"bad \\q escape\"", 1),
+            ("h = yes", 1),
+        ];
+        for (text, line) in cases {
+            let e = parse(text).unwrap_err().to_string();
+            assert_eq!(e, format!("line {}: invalid syntax", line), "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn apply() {
+        let config = Config::parse(concat!(
+            "spelling = true\n",
+            "case = \"sensitive\"\n",
+            "exclude_mimetypes = [\"image/png\"]\n",
+            "fts_properties = { \"nfo:fileName\" = 3 }\n",
+            "[commands]\n",
+            "gimp = \"gimp {path}\"\n",
+            "[keys]\n",
+            "custom-1 = \"gimp\"\n",
+            "custom-2 = \"select\"\n",
+        )).unwrap();
+        assert!(config.spelling);
+        assert_eq!(config.case, CaseMode::Sensitive);
+        assert_eq!(config.exclude_mimetypes, ["image/png"]);
+        assert_eq!(config.fts_properties, [(String::from("nfo:fileName"), 3)]);
+        assert_eq!(config.keys.len(), 2);
+        assert_eq!(config.keys.get(&2), Some(&Action::Select));
+    }
+
+    #[test]
+    fn invalid_values() {
+        let cases = [
+            ("spelling = 1", "line 1: 'spelling' should be a boolean, not a integer"),
+            ("exclude_mimetypes = [1]",
+             "line 1: 'exclude_mimetypes' should be a list of strings, not a list"),
+            ("[keys]\ncustom-99 = \"open\"", "line 2: invalid key 'custom-99'"),
+            ("[keys]\ncustom-1 = \"nope\"", "line 2: unknown action 'nope'"),
+            ("[max_per_category]\nimages = -1", "line 2: invalid quota for 'images'"),
+            ("[max_per_category]\nimage = 1", "line 2: unknown category 'image'"),
+            ("fts_properties = { \"bad name\" = 1 }",
+             "line 1: invalid property 'bad name'"),
+        ];
+        for (text, error) in cases {
+            let e = Config::parse(text).unwrap_err().to_string();
+            assert_eq!(e, error, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let mut config = Config::default();
+        for e in parse("no_such_option = 1\n[no_such_section]\n").unwrap() {
+            assert!(!config.apply(&e).unwrap(), "{}", e.key);
+        }
+        assert!(config.apply(&entry("spelling", Value::Bool(true), 1)).unwrap());
+
+        /* unknown keys are ignored when loading */
+        assert!(Config::parse("no_such_option = 1").is_ok());
+    }
+}
@@ -0,0 +1,74 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Reporting panics
+ *
+ * A panic would otherwise leave rofi with an empty (or partial) list, and
+ * the message on a stderr that nobody sees. Instead, we add a row with the
+ * error to rofi's list, and log the details to a file in the state dir.
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::panic;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::history;
+use crate::rofi::{Key, Line};
+
+pub fn log_path() -> Option<PathBuf> {
+    Some(history::state_dir()?.join("log"))
+}
+
+fn message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "unknown panic"
+    }
+}
+
+fn log(entry: &str) -> io::Result<()> {
+    let path = log_path().ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut f = OpenOptions::new().create(true).append(true).open(path)?;
+    f.write_all(entry.as_bytes())
+}
+
+/* install a panic hook that logs the panic, and when running under rofi
+ * (which sets ROFI_RETV for every invocation), reports it in a row */
+pub fn install() {
+    let default = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        let msg = message(info.payload());
+        let location = info
+            .location()
+            .map_or_else(String::new, |l| format!(" at {}:{}", l.file(), l.line()));
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let args = env::args().collect::<Vec<_>>().join(" ");
+
+        let _ = log(&format!("{} panic{}: {}\n  args: {}\n{}\n", time, location,
+                             msg, args, Backtrace::force_capture()));
+
+        if env::var_os("ROFI_RETV").is_some() {
+            let row = Line::row(&format!("internal error: {} (see log)", msg))
+                .flag(Key::Nonselectable, true)
+                .finish();
+            let _ = io::stdout().write_all(&row);
+            let _ = io::stdout().flush();
+        }
+
+        default(info);
+    }));
+}
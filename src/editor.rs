@@ -0,0 +1,101 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Opening text results in an editor, at the line of the match
+ *
+ * Tracker doesn't tell us where in a file the search terms matched, so we
+ * find the first line containing one of the terms ourselves, then start the
+ * configured editor with its own syntax for jumping to a line.
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::action;
+use crate::config::Config;
+use crate::history;
+use crate::launcher;
+
+/* the extra placeholder available in editor templates */
+pub const LINE_PLACEHOLDER: &str = "{line}";
+
+/* don't scan further than this into a file for a match */
+const MAX_SCAN_BYTES: u64 = 4 * 1024 * 1024;
+
+/* templates for editors we know about; others (or terminal editors, which
+ * need a terminal to run in) can be added in the [editors] config section */
+const TEMPLATES: &[(&str, &[&str])] = &[
+    ("code", &["code", "-g", "{path}:{line}"]),
+    ("emacsclient", &["emacsclient", "-n", "+{line}", "{path}"]),
+    ("gedit", &["gedit", "+{line}", "{path}"]),
+    ("gvim", &["gvim", "+{line}", "{path}"]),
+    ("kate", &["kate", "--line", "{line}", "{path}"]),
+];
+
+/* the command template for the configured editor, if any */
+pub fn template(config: &Config) -> Option<Vec<String>> {
+    let name = config.editor.as_deref()?;
+
+    if let Some((_, argv)) = config.editors.iter().find(|(n, _)| n == name) {
+        return Some(argv.clone());
+    }
+
+    TEMPLATES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, argv)| argv.iter().map(|a| a.to_string()).collect())
+}
+
+/* the (1-based) number of the first line containing any of the terms,
+ * ignoring case. Binary files have no lines to jump to */
+fn match_line(path: &Path, terms: &[String]) -> Option<usize> {
+    let mut buf = Vec::new();
+    File::open(path)
+        .and_then(|f| f.take(MAX_SCAN_BYTES).read_to_end(&mut buf))
+        .ok()?;
+
+    if buf.contains(&0) {
+        return None;
+    }
+
+    let terms = terms
+        .iter()
+        .map(|t| t.to_lowercase())
+        .filter(|t| !t.is_empty())
+        .collect::<Vec<_>>();
+
+    String::from_utf8_lossy(&buf)
+        .lines()
+        .position(|l| {
+            let l = l.to_lowercase();
+            terms.iter().any(|t| l.contains(t.as_str()))
+        })
+        .map(|n| n + 1)
+}
+
+/* open a text file in the editor at the first match of the search terms.
+ * Returns false if there's no editor configured, the file isn't text, or
+ * there's no match to go to, so the file should be opened as usual */
+pub fn open_at_match(config: &Config, uri: &str, terms: &[String])
+        -> anyhow::Result<bool> {
+    let argv = match template(config) {
+        Some(a) => a,
+        None => return Ok(false),
+    };
+
+    let line = match action::file_path(uri)
+        .ok()
+        .filter(|p| launcher::mimetype(p).is_some_and(|m| m.starts_with("text/")))
+        .and_then(|p| match_line(&p, terms)) {
+        Some(l) => l.to_string(),
+        None => return Ok(false),
+    };
+
+    if config.records_opens() {
+        let _ = history::record_open(uri);
+    }
+    action::run_template(&argv, uri, &[(LINE_PLACEHOLDER, OsStr::new(&line))])?;
+    Ok(true)
+}
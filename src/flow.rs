@@ -0,0 +1,181 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Multi-step interactions, with state carried between rofi invocations
+ *
+ * Each rofi invocation of tracker-rofi is a separate process, so any
+ * interaction longer than "search, then open" needs to carry state between
+ * invocations. A State is the name of a step, plus a set of string
+ * parameters, and is carried in two ways:
+ *
+ *  - as the `data` mode option, which rofi passes back to the next
+ *    invocation in ROFI_DATA. This is the state that typed input applies to.
+ *
+ *  - in the `info` of a transition row: selecting that row moves to the
+ *    row's state, rather than opening a result. rofi keeps every row's
+ *    info, so these should only carry what their step needs.
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use std::env;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+
+use crate::rofi::{self, Key};
+use crate::row::Row;
+
+/* prefix for row info strings that encode a state transition */
+const TRANSITION_PREFIX: &str = "flow:";
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct State {
+    pub step: String,
+    params: Vec<(String, String)>,
+}
+
+fn encode_str(s: &str) -> String {
+    utf8_percent_encode(s, NON_ALPHANUMERIC).to_string()
+}
+
+fn decode_str(s: &str) -> Option<String> {
+    percent_decode_str(s).decode_utf8().ok().map(|s| s.into_owned())
+}
+
+impl State {
+    pub fn new(step: &str) -> Self {
+        State { step: step.to_string(), params: Vec::new() }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self.params.iter_mut().find(|(k, _)| k == key) {
+            Some(p) => p.1 = value.to_string(),
+            None => self.params.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn with(mut self, key: &str, value: &str) -> Self {
+        self.set(key, value);
+        self
+    }
+
+    pub fn without(mut self, key: &str) -> Self {
+        self.params.retain(|(k, _)| k != key);
+        self
+    }
+
+    /* encoded as step?key=value&key=value, with all components
+     * percent-encoded, so the result is safe for rofi's protocol */
+    pub fn encode(&self) -> String {
+        let params = self.params
+            .iter()
+            .map(|(k, v)| format!("{}={}", encode_str(k), encode_str(v)))
+            .collect::<Vec<_>>()
+            .join("&");
+        format!("{}?{}", encode_str(&self.step), params)
+    }
+
+    pub fn decode(s: &str) -> Option<State> {
+        let (step, params) = s.split_once('?')?;
+
+        let params = params
+            .split('&')
+            .filter(|p| !p.is_empty())
+            .map(|p| {
+                let (k, v) = p.split_once('=')?;
+                Some((decode_str(k)?, decode_str(v)?))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(State { step: decode_str(step)?, params })
+    }
+
+    /* the state from the previous invocation, if any */
+    pub fn from_env() -> Option<State> {
+        State::decode(&env::var("ROFI_DATA").ok()?)
+    }
+
+    /* the mode option to pass this state to the next invocation */
+    pub fn data_option(&self) -> Vec<u8> {
+        rofi::mode_option(Key::Data, &self.encode())
+    }
+
+    /* a row that moves to this state when selected */
+    pub fn transition_row(&self, text: &str) -> Row {
+        let mut row = Row::new(text);
+        row.info = Some(format!("{}{}", TRANSITION_PREFIX, self.encode()));
+        row
+    }
+
+    /* the state from a selected transition row's info string */
+    pub fn from_transition(info: &str) -> Option<State> {
+        State::decode(info.strip_prefix(TRANSITION_PREFIX)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn awkward() -> State {
+        State::new("step & name?")
+            .with("query", "a&b=c?d%e")
+            .with("uris", "id1 file:///tmp/a%20b\nid2 file:///tmp/c")
+            .with("name", "Grüße, 日本語 ✓")
+            .with("empty", "")
+    }
+
+    #[test]
+    fn round_trip() {
+        let states = [
+            State::new("search"),
+            State::new("search").with("query", "report"),
+            awkward(),
+        ];
+        for s in states {
+            let encoded = s.encode();
+            assert!(encoded.is_ascii());
+            assert_eq!(State::decode(&encoded), Some(s));
+        }
+    }
+
+    #[test]
+    fn transition_round_trip() {
+        let s = awkward();
+        let info = s.transition_row("text").info.unwrap();
+        assert!(info.starts_with(TRANSITION_PREFIX));
+        assert_eq!(State::from_transition(&info), Some(s));
+    }
+
+    #[test]
+    fn without() {
+        let s = awkward().without("uris").without("missing");
+        assert_eq!(s.get("uris"), None);
+        assert_eq!(s.get("query"), Some("a&b=c?d%e"));
+    }
+
+    #[test]
+    fn malformed() {
+        let bad = [
+            "search",
+            "search?query",
+            "search?query=%ff",
+            "%ff?query=a",
+        ];
+        for s in bad {
+            assert_eq!(State::decode(s), None, "{}", s);
+        }
+
+        /* only rows with the prefix are transitions */
+        assert_eq!(State::from_transition("search?query=a"), None);
+        assert_eq!(State::from_transition("flow"), None);
+        assert_eq!(State::from_transition("flow:"), None);
+        assert_eq!(State::from_transition("flow:search"), None);
+        assert_eq!(State::from_transition("flow:search?"), Some(State::new("search")));
+        assert_eq!(State::from_transition("FLOW:search?"), None);
+    }
+}
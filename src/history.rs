@@ -0,0 +1,142 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* History of opened files and searches, and the last query
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/* how long an opened file is considered recent */
+const RECENT_PERIOD: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/* number of history entries to keep */
+const HISTORY_MAX: usize = 200;
+
+pub fn state_dir() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME")
+                 .map(|h| PathBuf::from(h).join(".local").join("state")))?;
+    Some(dir.join("tracker-rofi"))
+}
+
+/* the last query is only kept for the login session, so is in the runtime
+ * dir, rather than the state dir */
+fn last_query_path() -> PathBuf {
+    let dir = env::var_os("XDG_RUNTIME_DIR")
+        .map_or_else(env::temp_dir, PathBuf::from);
+    dir.join("tracker-rofi-last-query")
+}
+
+pub fn record_query(query: &str) -> std::io::Result<()> {
+    fs::write(last_query_path(), query)
+}
+
+pub fn last_query() -> Option<String> {
+    fs::read_to_string(last_query_path())
+        .ok()
+        .filter(|q| !q.trim().is_empty())
+}
+
+fn history_path() -> Option<PathBuf> {
+    Some(state_dir()?.join("opened"))
+}
+
+fn searches_path() -> Option<PathBuf> {
+    Some(state_dir()?.join("searches"))
+}
+
+/* queries that found results, one per line, oldest first */
+pub fn searches() -> Vec<String> {
+    match searches_path().map(fs::read_to_string) {
+        Some(Ok(t)) => t.lines().map(str::to_string).collect(),
+        _ => Vec::new(),
+    }
+}
+
+pub fn record_search(query: &str) -> std::io::Result<()> {
+    let path = match searches_path() {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+
+    let query = query.trim().replace('\n', " ");
+    let mut searches = searches();
+    searches.retain(|q| *q != query);
+    searches.push(query);
+
+    let skip = searches.len().saturating_sub(HISTORY_MAX);
+    let text = searches
+        .iter()
+        .skip(skip)
+        .map(|q| format!("{}\n", q))
+        .collect::<String>();
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, text)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/* entries are lines of "<timestamp> <uri>", oldest first */
+fn entries() -> Vec<(u64, String)> {
+    let text = match history_path().map(fs::read_to_string) {
+        Some(Ok(t)) => t,
+        _ => return Vec::new(),
+    };
+
+    text.lines()
+        .filter_map(|l| {
+            let (t, uri) = l.split_once(' ')?;
+            Some((t.parse().ok()?, uri.to_string()))
+        })
+        .collect()
+}
+
+pub fn record_open(uri: &str) -> std::io::Result<()> {
+    let path = match history_path() {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+
+    let mut entries = entries();
+    entries.retain(|(_, u)| u != uri);
+    entries.push((now(), uri.to_string()));
+
+    let skip = entries.len().saturating_sub(HISTORY_MAX);
+    let text = entries
+        .iter()
+        .skip(skip)
+        .map(|(t, u)| format!("{} {}\n", t, u))
+        .collect::<String>();
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, text)
+}
+
+/* all opened files in the history, as uris */
+pub fn opened() -> Vec<String> {
+    entries().into_iter().map(|(_, u)| u).collect()
+}
+
+pub fn recently_opened() -> HashSet<String> {
+    let cutoff = now().saturating_sub(RECENT_PERIOD.as_secs());
+
+    entries()
+        .into_iter()
+        .filter(|(t, _)| *t >= cutoff)
+        .map(|(_, u)| u)
+        .collect()
+}
@@ -0,0 +1,367 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Filtering results with gitignore-style pattern files
+ *
+ * Patterns follow the gitignore format: one pattern per line, with `#`
+ * comments, `!` to re-include a previously excluded path, a trailing `/` to
+ * match only directories, and a leading (or inner) `/` to anchor the
+ * pattern to the root of the file that it's in. Unanchored patterns match
+ * the name of the file, or of any of its parent directories. Globs may use
+ * `*`, `?`, `[...]` and `**`.
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::anyhow;
+
+use crate::config::Config;
+
+/* ignore files read from each root */
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    glob: Vec<char>,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+/* match a glob against a path, from the given positions */
+fn glob_match(g: &[char], p: &[char]) -> bool {
+    match g.first() {
+        None => p.is_empty(),
+        Some('*') if g.get(1) == Some(&'*') => {
+            /* "**" matches across directories, and with a following slash,
+             * may match no directories at all */
+            let rest = &g[2..];
+            if let Some(r) = rest.strip_prefix(&['/']) {
+                if glob_match(r, p) {
+                    return true;
+                }
+            }
+            (0..=p.len()).any(|i| glob_match(rest, &p[i..]))
+        }
+        Some('*') => {
+            let rest = &g[1..];
+            (0..=p.len())
+                .take_while(|i| *i == 0 || p[i - 1] != '/')
+                .any(|i| glob_match(rest, &p[i..]))
+        }
+        Some('?') => matches!(p.first(), Some(c) if *c != '/') && glob_match(&g[1..], &p[1..]),
+        Some('[') => {
+            let c = match p.first() {
+                Some(c) if *c != '/' => *c,
+                _ => return false,
+            };
+            match class_match(&g[1..], c) {
+                Some((true, len)) => glob_match(&g[1 + len..], &p[1..]),
+                _ => false,
+            }
+        }
+        Some('\\') if g.len() > 1 => p.first() == Some(&g[1]) && glob_match(&g[2..], &p[1..]),
+        Some(c) => p.first() == Some(c) && glob_match(&g[1..], &p[1..]),
+    }
+}
+
+/* match a character class (after the '['), returning whether it matched,
+ * and the length of the class including the closing ']' */
+fn class_match(g: &[char], c: char) -> Option<(bool, usize)> {
+    let (negated, start) = match g.first() {
+        Some('!') | Some('^') => (true, 1),
+        _ => (false, 0),
+    };
+
+    let mut i = start;
+    let mut matched = false;
+
+    loop {
+        let first = *g.get(i)?;
+        /* a ']' straight after the '[' is a literal */
+        if first == ']' && i > start {
+            return Some((matched != negated, i + 1));
+        }
+        if g.get(i + 1) == Some(&'-') && g.get(i + 2).is_some_and(|e| *e != ']') {
+            let last = g[i + 2];
+            matched |= (first..=last).contains(&c);
+            i += 3;
+        } else {
+            matched |= first == c;
+            i += 1;
+        }
+    }
+}
+
+impl Pattern {
+    pub fn parse(line: &str) -> anyhow::Result<Option<Pattern>> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(l) => (true, l),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(l) => (true, l),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+
+        if line.is_empty() {
+            return Err(anyhow!("empty pattern"));
+        }
+
+        let glob = line.chars().collect::<Vec<_>>();
+
+        /* check that character classes are closed */
+        for (i, _) in glob.iter().enumerate().filter(|(_, c)| **c == '[') {
+            if i > 0 && glob[i - 1] == '\\' {
+                continue;
+            }
+            if class_match(&glob[i + 1..], '\0').is_none() {
+                return Err(anyhow!("unterminated '[' in '{}'", line));
+            }
+        }
+
+        Ok(Some(Pattern { glob, negated, dir_only, anchored }))
+    }
+
+    /* match a path, relative to the pattern's root */
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let target = if self.anchored {
+            path
+        } else {
+            path.rsplit('/').next().unwrap_or(path)
+        };
+        glob_match(&self.glob, &target.chars().collect::<Vec<_>>())
+    }
+}
+
+/* a set of patterns, applying to paths under a root directory */
+#[derive(Debug)]
+struct PatternSet {
+    root: PathBuf,
+    patterns: Vec<Pattern>,
+}
+
+impl PatternSet {
+    /* is the path ignored? A path is ignored if it, or any of its parent
+     * directories, is matched by a pattern, and the last matching pattern
+     * isn't negated */
+    fn ignored(&self, path: &Path) -> bool {
+        let rel = match path.strip_prefix(&self.root) {
+            Ok(r) => r,
+            Err(_) => return false,
+        };
+
+        let components = rel
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>();
+
+        (1..=components.len()).any(|n| {
+            let sub = components[..n].join("/");
+            let is_dir = n < components.len();
+            self.patterns
+                .iter()
+                .rev()
+                .find(|p| p.matches(&sub, is_dir))
+                .is_some_and(|p| !p.negated)
+        })
+    }
+}
+
+fn parse_lines(text: &str) -> Vec<Pattern> {
+    /* unlike git, we skip invalid patterns, rather than failing the search */
+    text.lines()
+        .filter_map(|l| Pattern::parse(l).ok().flatten())
+        .collect()
+}
+
+/* the global ignore file */
+fn global_file() -> Option<PathBuf> {
+    Config::path().map(|p| p.with_file_name("ignore"))
+}
+
+/* the ignore files that apply, with the root that their patterns are
+ * relative to */
+fn ignore_files(config: &Config) -> Vec<(PathBuf, PathBuf)> {
+    if !config.ignore_files {
+        return Vec::new();
+    }
+
+    let global = global_file().map(|f| (PathBuf::from("/"), f));
+    let roots = config.ignore_roots
+        .iter()
+        .flat_map(|r| IGNORE_FILES.iter().map(move |f| (r.clone(), r.join(f))));
+
+    global.into_iter().chain(roots).collect()
+}
+
+/* report invalid patterns in ignore files */
+pub fn check(config: &Config) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for (_, file) in ignore_files(config) {
+        let text = match fs::read_to_string(&file) {
+            Ok(t) => t,
+            Err(_) => continue,
+        };
+        for (n, line) in text.lines().enumerate() {
+            if let Err(e) = Pattern::parse(line) {
+                problems.push(format!("{}: line {}: {}", file.display(), n + 1, e));
+            }
+        }
+    }
+
+    problems
+}
+
+#[derive(Debug, Default)]
+pub struct Ignores {
+    sets: Vec<PatternSet>,
+}
+
+impl Ignores {
+    /* the configured patterns, plus those from the global ignore file and
+     * each root's ignore files, when ignore files are enabled */
+    pub fn load(config: &Config) -> Ignores {
+        let mut sets: Vec<PatternSet> = Vec::new();
+
+        if !config.ignore.is_empty() {
+            sets.push(PatternSet {
+                root: PathBuf::from("/"),
+                patterns: config.ignore.clone(),
+            });
+        }
+
+        for (root, file) in ignore_files(config) {
+            let patterns = match fs::read_to_string(&file) {
+                Ok(t) => parse_lines(&t),
+                Err(_) => continue,
+            };
+            match sets.iter_mut().find(|s| s.root == root) {
+                Some(s) => s.patterns.extend(patterns),
+                None => sets.push(PatternSet { root, patterns }),
+            }
+        }
+
+        Ignores { sets }
+    }
+
+    pub fn ignored(&self, path: &Path) -> bool {
+        self.sets.iter().any(|s| s.ignored(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(g: &str, p: &str) -> bool {
+        glob_match(&g.chars().collect::<Vec<_>>(), &p.chars().collect::<Vec<_>>())
+    }
+
+    fn class(g: &str, c: char) -> Option<(bool, usize)> {
+        class_match(&g.chars().collect::<Vec<_>>(), c)
+    }
+
+    fn set(patterns: &[&str]) -> PatternSet {
+        PatternSet {
+            root: PathBuf::from("/root"),
+            patterns: patterns
+                .iter()
+                .filter_map(|p| Pattern::parse(p).unwrap())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn globs() {
+        let cases = [
+            ("*.o", "main.o", true),
+            ("*.o", "main.c", false),
+            ("*.o", "src/main.o", false),
+            ("*", "", true),
+            ("a*b*c", "axxbyyc", true),
+            ("a*b*c", "axxbyy", false),
+            ("**/build", "build", true),
+            ("**/build", "a/b/build", true),
+            ("src/**", "src/a/b.c", true),
+            ("a/**/b", "a/b", true),
+            ("a/**/b", "a/x/y/b", true),
+            ("a/**/b", "x/a/b", false),
+            ("?.c", "a.c", true),
+            ("?.c", "ab.c", false),
+            ("a?b", "a/b", false),
+            ("[a-z].c", "m.c", true),
+            ("[a-z].c", "M.c", false),
+            ("[!x].c", "y.c", true),
+            ("[!x].c", "x.c", false),
+            ("[^x].c", "x.c", false),
+            ("[]]", "]", true),
+            ("[a-]", "-", true),
+            ("a[/]b", "a/b", false),
+            ("\\*.c", "*.c", true),
+            ("\\*.c", "a.c", false),
+        ];
+        for (g, p, m) in cases {
+            assert_eq!(glob(g, p), m, "{} against {}", g, p);
+        }
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(class("abc]", 'b'), Some((true, 4)));
+        assert_eq!(class("abc]", 'd'), Some((false, 4)));
+        assert_eq!(class("!a-c]", 'd'), Some((true, 5)));
+        assert_eq!(class("0-9a-f]x", 'e'), Some((true, 7)));
+        assert_eq!(class("abc", 'a'), None);
+        assert_eq!(class("!", 'a'), None);
+    }
+
+    #[test]
+    fn unterminated_class() {
+        assert!(Pattern::parse("[abc").is_err());
+        assert!(Pattern::parse("\\[abc").unwrap().is_some());
+    }
+
+    #[test]
+    fn anchoring() {
+        let unanchored = set(&["build"]);
+        assert!(unanchored.ignored(Path::new("/root/build")));
+        assert!(unanchored.ignored(Path::new("/root/src/build/main.o")));
+
+        let anchored = set(&["/build"]);
+        assert!(anchored.ignored(Path::new("/root/build/main.o")));
+        assert!(!anchored.ignored(Path::new("/root/src/build/main.o")));
+
+        let inner = set(&["src/*.o"]);
+        assert!(inner.ignored(Path::new("/root/src/main.o")));
+        assert!(!inner.ignored(Path::new("/root/lib/src/main.o")));
+
+        assert!(!unanchored.ignored(Path::new("/other/build")));
+    }
+
+    #[test]
+    fn directories() {
+        let s = set(&["cache/"]);
+        assert!(s.ignored(Path::new("/root/cache/data")));
+        assert!(s.ignored(Path::new("/root/a/cache/data")));
+        assert!(!s.ignored(Path::new("/root/cache")));
+    }
+
+    #[test]
+    fn negation() {
+        let s = set(&["*.log", "!keep.log"]);
+        assert!(s.ignored(Path::new("/root/debug.log")));
+        assert!(!s.ignored(Path::new("/root/keep.log")));
+    }
+}
@@ -0,0 +1,197 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Launching multiple files, grouped by their handler application
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use anyhow::{anyhow, Context};
+use url::Url;
+
+use crate::action;
+use crate::config::Config;
+use crate::history;
+
+fn xdg_mime<S: AsRef<OsStr>>(args: &[S]) -> Option<String> {
+    let output = Command::new("xdg-mime")
+        .args(args)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let s = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    if s.is_empty() { None } else { Some(s) }
+}
+
+pub fn mimetype(path: &Path) -> Option<String> {
+    xdg_mime(&[OsStr::new("query"), OsStr::new("filetype"), path.as_os_str()])
+}
+
+/* the desktop file id of the default handler for a mimetype */
+pub fn default_for(mimetype: &str) -> Option<String> {
+    xdg_mime(&["query", "default", mimetype])
+}
+
+/* the desktop file id of the default handler for a file */
+fn default_handler(path: &Path) -> Option<String> {
+    default_for(&mimetype(path)?)
+}
+
+fn data_dirs() -> Vec<PathBuf> {
+    let home = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME")
+                 .map(|h| PathBuf::from(h).join(".local").join("share")));
+
+    let dirs = env::var("XDG_DATA_DIRS")
+        .unwrap_or_else(|_| String::from("/usr/local/share:/usr/share"));
+
+    home.into_iter()
+        .chain(dirs.split(':').filter(|d| !d.is_empty()).map(PathBuf::from))
+        .collect()
+}
+
+pub fn desktop_file(id: &str) -> Option<PathBuf> {
+    data_dirs()
+        .into_iter()
+        .map(|d| d.join("applications").join(id))
+        .find(|p| p.exists())
+}
+
+/* the value of a key in a desktop file's [Desktop Entry] group */
+fn desktop_entry_value(path: &Path, key: &str) -> Option<String> {
+    let text = fs::read_to_string(path).ok()?;
+    let mut in_entry = false;
+
+    for line in text.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_entry = line == "[Desktop Entry]";
+        } else if in_entry {
+            if let Some((k, v)) = line.split_once('=') {
+                if k.trim() == key {
+                    return Some(v.trim().to_string());
+                }
+            }
+        }
+    }
+    None
+}
+
+#[derive(Debug)]
+pub struct Handler {
+    /* desktop file id, as passed to xdg-mime */
+    pub id: String,
+    pub name: String,
+}
+
+/* applications that can handle a mimetype, from the desktop database
+ * (mimeinfo.cache) in each data directory. Hidden entries are skipped */
+pub fn handlers(mimetype: &str) -> Vec<Handler> {
+    let prefix = format!("{}=", mimetype);
+    let mut ids: Vec<String> = Vec::new();
+
+    for dir in data_dirs() {
+        let cache = dir.join("applications").join("mimeinfo.cache");
+        let text = match fs::read_to_string(cache) {
+            Ok(t) => t,
+            Err(_) => continue,
+        };
+        for list in text.lines().filter_map(|l| l.strip_prefix(prefix.as_str())) {
+            for id in list.split(';').filter(|id| !id.is_empty()) {
+                if !ids.iter().any(|i| i == id) {
+                    ids.push(id.to_string());
+                }
+            }
+        }
+    }
+
+    let mut handlers = ids
+        .into_iter()
+        .filter_map(|id| {
+            let file = desktop_file(&id)?;
+            let hidden = ["Hidden", "NoDisplay"]
+                .iter()
+                .any(|k| desktop_entry_value(&file, k).as_deref() == Some("true"));
+            if hidden {
+                return None;
+            }
+            let name = desktop_entry_value(&file, "Name")
+                .unwrap_or_else(|| id.trim_end_matches(".desktop").to_string());
+            Some(Handler { id, name })
+        })
+        .collect::<Vec<_>>();
+
+    handlers.sort_by_key(|h| h.name.to_lowercase());
+    handlers
+}
+
+/* make an application the default handler for a mimetype */
+pub fn set_default(id: &str, mimetype: &str) -> anyhow::Result<()> {
+    let status = Command::new("xdg-mime")
+        .args(["default", id, mimetype])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .context("can't run xdg-mime")?;
+
+    if !status.success() {
+        return Err(anyhow!("xdg-mime failed to set {} for {}", id, mimetype));
+    }
+    Ok(())
+}
+
+/* open a set of files, passing all files with the same handler to a single
+ * invocation of that handler. Files without a known handler (or non-local
+ * files) are opened individually */
+pub fn open_grouped(uris: &[String], config: &Config) -> anyhow::Result<()> {
+    let mut groups: Vec<(PathBuf, Vec<PathBuf>)> = Vec::new();
+    let mut others = Vec::new();
+
+    for uri in uris {
+        if config.records_opens() {
+            let _ = history::record_open(uri);
+        }
+        let path = Url::parse(uri).ok().and_then(|u| u.to_file_path().ok());
+        let handler = path
+            .as_ref()
+            .and_then(|p| default_handler(p))
+            .and_then(|id| desktop_file(&id));
+
+        match (path, handler) {
+            /* paths are passed as-is, as they may not be UTF-8 */
+            (Some(p), Some(h)) => {
+                match groups.iter_mut().find(|(g, _)| *g == h) {
+                    Some((_, files)) => files.push(p),
+                    None => groups.push((h, vec![p])),
+                }
+            }
+            _ => others.push(uri),
+        }
+    }
+
+    for (handler, files) in groups {
+        Command::new("gio")
+            .arg("launch")
+            .arg(&handler)
+            .args(&files)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("can't launch {}", handler.display()))?;
+    }
+
+    for uri in others {
+        action::open_uri(uri, config)?;
+    }
+
+    Ok(())
+}
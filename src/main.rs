@@ -5,46 +5,172 @@
  */
 
 use std::env;
-use std::time::Duration;
-use std::collections::HashMap;
-use std::io::{self, Read, Write};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 use anyhow::{anyhow, Context};
-use dbus::blocking::Connection;
-use dbus::Message;
-use dbus::arg::Variant;
-use fork::{daemon, Fork};
-use opener;
-use percent_encoding::percent_decode_str;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
 use url::Url;
-use fd::Pipe;
 
-use nom::number::complete::u32;
-use nom::bytes::complete::tag;
-use nom::multi::{count};
-use nom::sequence::tuple;
+mod action;
+mod cache;
+mod category;
+mod child;
+mod cli;
+mod clipboard;
+mod config;
+mod crash;
+mod editor;
+mod flow;
+mod history;
+mod ignore;
+mod launcher;
+mod notify;
+mod period;
+mod pim;
+mod project;
+mod playlist;
+mod rofi;
+mod row;
+mod sparql;
+mod spell;
+mod stem;
+mod trace;
+mod tracker;
+use category::Category;
+use config::{CaseMode, Config, ExportFormat};
+use ignore::Ignores;
+use period::Period;
+use rofi::{Key, Line};
+use row::{Row, State};
+use sparql::Select;
 
-const DBUS_TIMEOUT: Duration = Duration::from_millis(2000);
+/* maximum number of results to export, or add to a playlist */
+const EXPORT_LIMIT: usize = 1000;
+
+/* number of results to show */
+const RESULT_LIMIT: usize = 15;
+
+/* when applying per-category quotas, we query for a larger set of results,
+ * so that results dropped from a full category can be replaced */
+const QUOTA_QUERY_FACTOR: usize = 4;
 
 #[derive(Debug)]
 struct QueryResult {
     uuid: String,
     uri: Url,
+    /* the URI as stored by Tracker, which we use to open the file, in case
+     * parsing has changed its encoding */
+    raw_uri: String,
     title: String,
-    _snippet: String,
+    snippet: String,
+    mimetype: String,
+    available: bool,
+    favorite: bool,
+    /* size in bytes, if known */
+    size: Option<u64>,
+    /* whether another result has the same size and title */
+    duplicate: bool,
 }
 
+/* number of columns in a search query */
+const SEARCH_COLUMNS: usize = 8;
+
 impl QueryResult {
-    fn new(uuid: &str, uristr: &str, title: &str, snippet: &str) -> Option<Self> {
+    /* construct from a row of search results: urn, uri, title, snippet,
+     * mimetype, availability, favorite tag, size */
+    fn from_row(row: &[&str]) -> Option<Self> {
+        if row.len() != SEARCH_COLUMNS {
+            return None;
+        }
+
+        Some(QueryResult {
+            uuid: row[0].to_string(),
+            uri: Url::parse(row[1]).ok()?,
+            raw_uri: row[1].to_string(),
+            title: row[2].to_string(),
+            snippet: row[3].to_string(),
+            mimetype: row[4].to_string(),
+            available: row[5] == "true",
+            favorite: !row[6].is_empty(),
+            size: row[7].parse().ok(),
+            duplicate: false,
+        })
+    }
+
+    /* a result for a known uri, without the metadata from a search */
+    fn from_uri(uuid: &str, raw_uri: &str) -> Option<Self> {
         Some(QueryResult {
             uuid: uuid.to_string(),
-            uri: Url::parse(uristr).ok()?,
-            title: title.to_string(),
-            _snippet: snippet.to_string(),
+            uri: Url::parse(raw_uri).ok()?,
+            raw_uri: raw_uri.to_string(),
+            title: String::new(),
+            snippet: String::new(),
+            mimetype: String::new(),
+            available: true,
+            favorite: false,
+            size: None,
+            duplicate: false,
         })
     }
 
+    fn states(&self, recent: &HashSet<String>) -> Vec<State> {
+        let mut states = Vec::new();
+
+        if !self.available {
+            states.push(State::Offline);
+        }
+
+        if let Ok(path) = self.uri.to_file_path() {
+            if self.available && !path.exists() {
+                states.push(State::Missing);
+            }
+        }
+
+        if self.favorite {
+            states.push(State::Favorite);
+        }
+
+        if recent.contains(self.uri.as_str()) {
+            states.push(State::Recent);
+        }
+
+        if self.duplicate {
+            states.push(State::Duplicate);
+        }
+
+        states
+    }
+
+    /* whether the file, or any of its parent directories, is hidden */
+    fn hidden(&self) -> bool {
+        match self.uri.path_segments() {
+            Some(mut segs) => segs.any(|s| decode_segment(s).starts_with('.')),
+            None => false,
+        }
+    }
+
+    fn file_name(&self) -> Option<String> {
+        Some(decode_segment(self.uri.path_segments()?.next_back()?))
+    }
+
+    /* the file name's extension, in lowercase */
+    fn extension(&self) -> Option<String> {
+        let fname = self.file_name()?;
+        let (stem, ext) = fname.rsplit_once('.')?;
+        if stem.is_empty() { None } else { Some(ext.to_lowercase()) }
+    }
+
+    fn category(&self) -> Category {
+        Category::from_mimetype(&self.mimetype)
+    }
+
     fn description(&self) -> String {
-        let decode = |s| percent_decode_str(s).decode_utf8_lossy();
+        let decode = decode_segment;
 
         let (fname, pname) = match self.uri.path_segments() {
             Some(mut c) => {
@@ -61,7 +187,7 @@ impl QueryResult {
             s += format!("{}: ", f).as_str();
         }
 
-        if self.title.len() > 0 {
+        if !self.title.is_empty() {
             s += &self.title;
         }
 
@@ -74,190 +200,1945 @@ impl QueryResult {
 }
 
 
-fn sparql_escape(s: &str) -> String {
-    s
-        .replace('\\', r#"\\"#)
-        .replace('"',  r#"\""#)
-        .replace('\'', r#"\'"#)
+/* a percent-encoded path segment, for display. Paths aren't necessarily
+ * UTF-8; for those that aren't, latin-1 is a better guess than replacement
+ * characters */
+fn decode_segment(s: &str) -> String {
+    let bytes = percent_decode_str(s).collect::<Vec<u8>>();
+    match String::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(e) => e.as_bytes().iter().map(|b| *b as char).collect(),
+    }
+}
+
+/* which properties the search terms need to appear in */
+#[derive(Debug, PartialEq)]
+enum SearchMode {
+    All,
+    Title,
+    Content,
+}
+
+#[derive(Debug)]
+struct SearchQuery {
+    mode: SearchMode,
+    text: String,
+    /* graphs from graph:<name> tokens */
+    graphs: Vec<String>,
+    /* only match favorites, for the :starred command */
+    starred: bool,
+    /* only match files in the current project, for the :here command */
+    here: bool,
+    /* whether to show hidden files, from a hidden:on|off token */
+    hidden: Option<bool>,
+    /* filename and path fragments, from name: and path: tokens */
+    names: Vec<String>,
+    paths: Vec<String>,
+    /* file types, from type: tokens */
+    types: Vec<FileType>,
+    /* where to take snippets from, from a snippet: token */
+    snippet: Option<SnippetSource>,
+}
+
+/* the property shown as a result's snippet, instead of Tracker's choice */
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SnippetSource {
+    Title,
+    Content,
+}
+
+/* context to show before the first match, in content snippets */
+const SNIPPET_LEAD: usize = 40;
+const SNIPPET_LEN: usize = 160;
+
+impl SnippetSource {
+    fn parse(s: &str) -> Option<SnippetSource> {
+        match s {
+            "title" => Some(SnippetSource::Title),
+            "content" => Some(SnippetSource::Content),
+            _ => None,
+        }
+    }
+
+    /* patterns binding ?snipsrc, and for content, ?snippos: the length of
+     * the text before the first match of a term */
+    fn patterns(&self, term: Option<&str>) -> Vec<String> {
+        let src = match self {
+            SnippetSource::Title => "?s nie:title | nie:interpretedAs/nie:title ?snipsrc .",
+            SnippetSource::Content =>
+                "?s nie:plainTextContent | nie:interpretedAs/nie:plainTextContent ?snipsrc .",
+        };
+        let mut p = vec![format!("OPTIONAL {{ {} }}", src)];
+
+        if *self == SnippetSource::Content {
+            let pos = match term {
+                Some(t) => format!(
+                    r#"STRLEN(STRBEFORE(LCASE(COALESCE(?snipsrc, "")), LCASE({})))"#,
+                    sparql::literal(t)),
+                None => String::from("0"),
+            };
+            p.push(format!("BIND ({} AS ?snippos)", pos));
+        }
+        p
+    }
+
+    /* the snippet expression: a whole title, or the content around the
+     * first match */
+    fn expr(&self) -> String {
+        match self {
+            SnippetSource::Title => String::from(r#"(COALESCE(?snipsrc, "") AS ?snippet)"#),
+            SnippetSource::Content => format!(
+                concat!(r#"(SUBSTR(COALESCE(?snipsrc, ""), "#,
+                        "IF(?snippos > {lead}, ?snippos - {lead} + 1, 1), {len}) AS ?snippet)"),
+                lead = SNIPPET_LEAD, len = SNIPPET_LEN),
+        }
+    }
+}
+
+/* a file type for a type: token: a category, or a file extension */
+#[derive(Debug, Clone, PartialEq)]
+enum FileType {
+    Category(Category),
+    Extension(String),
+}
+
+impl FileType {
+    fn parse(s: &str) -> Option<FileType> {
+        if let Some(c) = Category::from_type_name(s) {
+            return Some(FileType::Category(c));
+        }
+        let valid = !s.is_empty() && s.len() <= 10 &&
+            s.chars().all(|c| c.is_ascii_alphanumeric());
+        if valid {
+            Some(FileType::Extension(s.to_lowercase()))
+        } else {
+            None
+        }
+    }
+
+    /* a condition on the result's mimetype and file name variables */
+    fn cond(&self, mime: &str, fname: &str) -> String {
+        match self {
+            FileType::Category(c) => format!("({})", c.sparql_cond(mime)),
+            FileType::Extension(e) => format!("STRENDS(LCASE({}), {})", fname,
+                                              sparql::literal(&format!(".{}", e))),
+        }
+    }
+}
+
+/* words in a query that hint at the type of file wanted, when automatic
+ * types are enabled */
+const TYPE_HINTS: &[(&str, Category)] = &[
+    ("photo", Category::Images),
+    ("photos", Category::Images),
+    ("picture", Category::Images),
+    ("pictures", Category::Images),
+    ("screenshot", Category::Images),
+    ("screenshots", Category::Images),
+    ("song", Category::Audio),
+    ("songs", Category::Audio),
+    ("music", Category::Audio),
+    ("track", Category::Audio),
+    ("tracks", Category::Audio),
+    ("video", Category::Video),
+    ("videos", Category::Video),
+    ("movie", Category::Video),
+    ("movies", Category::Video),
+    ("document", Category::Documents),
+    ("documents", Category::Documents),
+    ("folder", Category::Folders),
+    ("folders", Category::Folders),
+];
+
+/* hints that are also extensions */
+const EXTENSION_HINTS: &[&str] = &["pdf"];
+
+/* words joining a hint to the rest of the query ("photo of ...") */
+const HINT_JOINERS: &[&str] = &["of", "from", "by", "about"];
+
+/* completions offered for tokens with a fixed set of values */
+const TOKEN_SUGGESTIONS: &[(&str, &[&str])] = &[
+    ("type", &["image", "audio", "video", "document", "archive", "folder", "pdf"]),
+    ("hidden", &["on", "off"]),
+    ("snippet", &["title", "content"]),
+];
+
+/* the minimum length of a partial token to offer suggestions for */
+const SUGGEST_MIN_LEN: usize = 3;
+
+/* the maximum number of suggestions to show */
+const SUGGEST_MAX: usize = 8;
+
+/* queries completing a partial token at the end of a query, eg. "typ" or
+ * "type:im" */
+fn suggestions(query: &str) -> Vec<String> {
+    let (head, word) = match query.trim_end().rsplit_once(' ') {
+        Some((h, w)) => (format!("{} ", h), w),
+        None => (String::new(), query.trim_end()),
+    };
+
+    if word.len() < SUGGEST_MIN_LEN {
+        return Vec::new();
+    }
+
+    TOKEN_SUGGESTIONS
+        .iter()
+        .flat_map(|(key, values)| values.iter().map(move |v| format!("{}:{}", key, v)))
+        .filter(|t| t.starts_with(word) && t != word)
+        .take(SUGGEST_MAX)
+        .map(|t| format!("{}{}", head, t))
+        .collect()
+}
+
+/* split key:<value> tokens, which may appear anywhere in the query, from
+ * the rest of the query text. Tokens with values that don't parse are left
+ * in the text */
+fn take_tokens<T, F>(q: &str, key: &str, parse: F) -> (String, Vec<T>)
+where
+    F: Fn(&str) -> Option<T>
+{
+    let prefix = format!("{}:", key);
+    let mut values = Vec::new();
+
+    let words = q.split_whitespace()
+        .filter(|w| match w.strip_prefix(&prefix).and_then(&parse) {
+            Some(v) => { values.push(v); false }
+            None => true,
+        })
+        .collect::<Vec<_>>();
+
+    if values.is_empty() {
+        return (q.to_string(), values);
+    }
+
+    (words.join(" "), values)
+}
+
+fn parse_on_off(s: &str) -> Option<bool> {
+    match s {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
+
+/* FTS query syntax that isn't a search word */
+const FTS_OPERATORS: &[&str] = &["AND", "OR", "NOT", "NEAR"];
+
+/* the stem of a plain search word, when stemming is configured */
+fn stemmed(word: &str, config: &Config) -> Option<String> {
+    let lang = config.stem_language.as_deref()?;
+    if !word.chars().all(char::is_alphabetic) || FTS_OPERATORS.contains(&word) {
+        return None;
+    }
+    stem::stem(word, lang)
+}
+
+/* the rest of a query starting with a :command */
+fn take_command<'a>(q: &'a str, cmd: &str) -> Option<&'a str> {
+    match q.strip_prefix(cmd) {
+        Some(t) if t.is_empty() || t.starts_with(' ') => Some(t.trim_start()),
+        _ => None,
+    }
+}
+
+impl SearchQuery {
+    fn parse(q: &str) -> Self {
+        let (q, graphs) = take_tokens(q.trim(), "graph", sparql::graph_name);
+        let (q, hidden) = take_tokens(&q, "hidden", parse_on_off);
+        let nonempty = |s: &str| Some(s.to_string()).filter(|s| !s.is_empty());
+        let (q, names) = take_tokens(&q, "name", nonempty);
+        let (q, paths) = take_tokens(&q, "path", nonempty);
+        let (q, types) = take_tokens(&q, "type", FileType::parse);
+        let (q, snippet) = take_tokens(&q, "snippet", SnippetSource::parse);
+
+        /* leading commands, in any order */
+        let (mut q, mut starred, mut here) = (q.as_str(), false, false);
+        loop {
+            if let Some(t) = take_command(q, ":starred") {
+                starred = true;
+                q = t;
+            } else if let Some(t) = take_command(q, ":here") {
+                here = true;
+                q = t;
+            } else {
+                break;
+            }
+        }
+
+        let (mode, text) = if let Some(t) = q.strip_prefix("title:") {
+            (SearchMode::Title, t.trim())
+        } else if let Some(t) = q.strip_prefix("content:") {
+            (SearchMode::Content, t.trim())
+        } else {
+            (SearchMode::All, q)
+        };
+
+        SearchQuery {
+            mode,
+            text: text.to_string(),
+            graphs,
+            starred,
+            here,
+            hidden: hidden.last().copied(),
+            names,
+            paths,
+            types,
+            snippet: snippet.last().copied(),
+        }
+    }
+
+    /* apply a type from a hint word in the query text, removing the hint
+     * (and any joining word after it) from the text. Returns the hint, for
+     * queries without type: tokens that have one */
+    fn detect_type(&mut self) -> Option<String> {
+        if self.mode != SearchMode::All || !self.types.is_empty() {
+            return None;
+        }
+
+        let words = self.text.split_whitespace().collect::<Vec<_>>();
+        let (i, ty) = words.iter().enumerate().find_map(|(i, w)| {
+            let w = w.to_lowercase();
+            if let Some((_, c)) = TYPE_HINTS.iter().find(|(h, _)| *h == w) {
+                return Some((i, FileType::Category(*c)));
+            }
+            if EXTENSION_HINTS.contains(&w.as_str()) {
+                return Some((i, FileType::Extension(w)));
+            }
+            None
+        })?;
+
+        let hint = words[i].to_string();
+        let joined = words.get(i + 1).is_some_and(|w| HINT_JOINERS.contains(w));
+        let end = if joined { i + 2 } else { i + 1 };
+
+        self.text = words[..i]
+            .iter()
+            .chain(&words[end..])
+            .copied()
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.types.push(ty);
+
+        Some(hint)
+    }
+
+    fn show_hidden(&self, config: &Config) -> bool {
+        self.hidden.unwrap_or(config.show_hidden)
+    }
+
+    /* graphs to search: those from the query, else those configured */
+    fn graphs<'a>(&'a self, config: &'a Config) -> &'a [String] {
+        if self.graphs.is_empty() { &config.graphs } else { &self.graphs }
+    }
+
+    /* the string to pass to fts:match. Content searches are for a literal
+     * fragment, so match as a FTS phrase, which ignores any punctuation */
+    fn fts_term(&self, config: &Config) -> String {
+        match self.mode {
+            SearchMode::Content => format!("\"{}\"", self.text.replace('"', "\"\"")),
+            _ if config.stem_language.is_none() => self.text.clone(),
+            _ => self.text
+                .split_whitespace()
+                .map(|w| stemmed(w, config).map_or(w.to_string(), |s| s + "*"))
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+
+    /* properties to match the search terms against, and their weights */
+    fn properties(&self, config: &Config) -> Vec<(String, i64)> {
+        let props = match self.mode {
+            SearchMode::All => return config.fts_properties.clone(),
+            SearchMode::Title => vec!["nie:title", "nfo:fileName"],
+            SearchMode::Content => vec!["nie:plainTextContent"],
+        };
+
+        props
+            .into_iter()
+            .map(|p| {
+                let w = config.fts_properties
+                    .iter()
+                    .find(|(n, _)| n == p)
+                    .map_or(1, |(_, w)| *w);
+                (p.to_string(), w)
+            })
+            .collect()
+    }
+
+    fn terms(&self, config: &Config) -> Vec<String> {
+        match self.mode {
+            SearchMode::Content => vec![self.text.clone()],
+            _ => self.text
+                .split_whitespace()
+                .map(|w| stemmed(w, config).unwrap_or_else(|| w.to_string()))
+                .collect(),
+        }
+    }
+
+    /* the plain terms that a match contains, as alternatives (from OR
+     * operators) of terms that must all appear. The other FTS operators,
+     * and terms excluded by NOT, aren't included, and quotes, wildcards and
+     * brackets are stripped from the terms */
+    fn term_groups(&self, config: &Config) -> Vec<Vec<String>> {
+        if self.mode == SearchMode::Content {
+            return vec![self.terms(config)];
+        }
+
+        let mut groups = vec![Vec::new()];
+        let mut negated = false;
+
+        for t in self.terms(config) {
+            match t.as_str() {
+                "OR" => groups.push(Vec::new()),
+                "NOT" => negated = true,
+                _ if FTS_OPERATORS.contains(&t.as_str()) => (),
+                _ => {
+                    let t = t.strip_prefix("NEAR(").unwrap_or(&t)
+                        .trim_matches(|c: char| "\"*()".contains(c));
+                    let excluded = std::mem::replace(&mut negated, false);
+                    if let (false, false, Some(g)) = (t.is_empty(), excluded,
+                                                       groups.last_mut()) {
+                        g.push(t.to_string());
+                    }
+                }
+            }
+        }
+
+        groups.retain(|g| !g.is_empty());
+        groups
+    }
+
+    /* terms to find in a file's text, to locate a match */
+    fn match_terms(&self, config: &Config) -> Vec<String> {
+        self.term_groups(config).into_iter().flatten().collect()
+    }
+
+    /* add patterns to restrict the fts:match results to the properties
+     * we're searching. The boolean structure of the query is kept, so
+     * that any alternative may match */
+    fn restrict(&self, config: &Config, mut q: Select) -> Select {
+        let props = self.properties(config);
+        let groups = self.term_groups(config);
+        if props.is_empty() || groups.is_empty() {
+            return q;
+        }
+
+        for (i, (p, _)) in props.iter().enumerate() {
+            q = q.optional(&format!("?s {} ?m{} .", p, i));
+        }
+
+        let in_props = |t: &String| {
+            let c = (0..props.len())
+                .map(|i| contains_cond(i, t))
+                .collect::<Vec<_>>()
+                .join(" || ");
+            format!("({})", c)
+        };
+
+        let conds = groups
+            .iter()
+            .map(|g| format!("({})", g.iter().map(in_props).collect::<Vec<_>>().join(" && ")))
+            .collect::<Vec<_>>()
+            .join(" || ");
+
+        q.filter(&conds)
+    }
+
+    /* rank by the weights of the properties that each term matches */
+    fn score(&self, config: &Config) -> Option<String> {
+        let props = self.properties(config);
+        let terms = self.match_terms(config);
+        if props.is_empty() || terms.is_empty() {
+            return None;
+        }
+
+        let score = terms
+            .iter()
+            .flat_map(|t| props
+                .iter()
+                .enumerate()
+                .map(move |(i, (_, w))|
+                    format!("IF({}, {}, 0)", contains_cond(i, t), w)))
+            .collect::<Vec<_>>()
+            .join(" + ");
+
+        Some(score)
+    }
+}
+
+/* a filter excluding the configured mimetypes, given the variable holding
+ * each result's (optional) mimetype */
+fn mimetype_filter(config: &Config, var: &str) -> Option<String> {
+    if config.exclude_mimetypes.is_empty() {
+        return None;
+    }
+
+    let types = config.exclude_mimetypes
+        .iter()
+        .map(|m| sparql::literal(m))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    Some(format!("!BOUND({0}) || {0} NOT IN ({1})", var, types))
 }
 
-//fn parse_one(buf: &[u8]) -> IResult<&[u8], (String, String, String, String)> {
-fn parse_one(buf: &[u8]) -> nom::IResult<&[u8], QueryResult> {
-    let p = u32(nom::number::Endianness::Native);
+/* characters encoded in file URLs */
+const URL_PATH_SET: &AsciiSet = &CONTROLS
+    .add(b' ').add(b'"').add(b'#').add(b'%').add(b'<').add(b'>').add(b'?')
+    .add(b'`').add(b'{').add(b'}');
 
-    let (b, _) = tag([4u8, 0, 0, 0])(buf)?;
-    let (b, _types) = count(p, 4)(b)?;
-    let (mut b, lengths) = count(p, 4)(b)?;
+/* whether to match text case-sensitively, in the configured case mode */
+fn case_sensitive(text: &str, config: &Config) -> bool {
+    match config.case {
+        CaseMode::Smart => text.chars().any(char::is_uppercase),
+        CaseMode::Sensitive => true,
+        CaseMode::Insensitive => false,
+    }
+}
 
-    let mut offset = 0;
-    let mut res = Vec::new();
+/* match part of a filename, case-sensitively depending on the configured
+ * case mode */
+fn name_cond(var: &str, text: &str, config: &Config) -> String {
+    if case_sensitive(text, config) {
+        format!("CONTAINS({}, {})", var, sparql::literal(text))
+    } else {
+        format!("CONTAINS(LCASE({}), LCASE({}))", var, sparql::literal(text))
+    }
+}
 
-    for l in lengths {
-        let len = l - offset;
-        let (bp, x) = nom::bytes::complete::take(len)(b)?;
-        let (bp, _) = nom::bytes::complete::tag(&[0u8])(bp)?;
-        b = bp;
-        res.push(std::str::from_utf8(x).unwrap());
-        offset += len + 1;
+/* match part of a path in a result's (percent-encoded) uri. The case mode
+ * applies to the path as typed; case-insensitive matches fold the path
+ * before encoding it, then compare with the lowercased uri, so escapes
+ * are lowercase on both sides. LCASE can't fold non-ASCII letters in the
+ * uri's escapes, so those only match in the case they're typed */
+fn path_cond(path: &str, config: &Config) -> String {
+    if case_sensitive(path, config) {
+        let p = utf8_percent_encode(path, URL_PATH_SET).to_string();
+        format!("CONTAINS(?uri, {})", sparql::literal(&p))
+    } else {
+        let p = utf8_percent_encode(&path.to_lowercase(), URL_PATH_SET)
+            .to_string()
+            .to_lowercase();
+        format!("CONTAINS(LCASE(?uri), {})", sparql::literal(&p))
     }
+}
 
-    let qr = QueryResult::new(res[0], res[1], res[2], res[3]).unwrap();
+fn contains_cond(idx: usize, term: &str) -> String {
+    format!(r#"CONTAINS(LCASE(COALESCE(?m{}, "")), LCASE({}))"#,
+            idx, sparql::literal(term))
+}
 
-    Ok((b, qr))
+/* how much of each result a search query fetches: everything, just
+ * enough to list results quickly, or only the number of results of each
+ * mimetype */
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Detail {
+    Full,
+    Fast,
+    Count,
 }
 
-fn tracker_search_v3(q: &str) -> anyhow::Result<Vec<QueryResult>> {
-    let conn = Connection::new_session()?;
-    let mut pipe = Pipe::new()?;
-    let args : HashMap<&str,Variant<u32>> = HashMap::new();
+fn search_query_v3(q: &SearchQuery, config: &Config, offset: usize,
+                   limit: usize, detail: Detail) -> anyhow::Result<String> {
+    /* :starred, :here and name:/path:/type: queries may have no text to
+     * match */
+    let fts = !q.text.is_empty() ||
+        !(q.starred || q.here || !q.names.is_empty() || !q.paths.is_empty() ||
+          !q.types.is_empty());
+    let full = detail == Detail::Full;
+
+    let snippet = match (q.snippet, full) {
+        (Some(src), true) => src.expr(),
+        (None, true) if fts => String::from(r#"(fts:snippet(?s, "", "") AS ?snippet)"#),
+        _ => String::from(r#"("" AS ?snippet)"#),
+    };
+    let (mime, size) = if full {
+        ("?mime", "?size")
+    } else {
+        (r#"("" AS ?mime)"#, r#"("" AS ?size)"#)
+    };
+
+    let mut query = match detail {
+        Detail::Count => Select::new(&["?mime", "(COUNT(DISTINCT ?s) AS ?n)"]),
+        _ => Select::new(&["?s", "?uri", "?title", &snippet, mime,
+                           "?available", "?fav", size])
+            .distinct(),
+    };
+
+    if fts {
+        query = query.pattern(&format!("?s fts:match {} .",
+                                       sparql::literal(&q.fts_term(config))));
+    }
+
+    if q.starred {
+        query = query.pattern(concat!("?s nao:hasTag | nie:interpretedAs/nao:hasTag",
+                                      " nao:predefined-tag-favorite ."));
+    }
+
+    let graphs = q.graphs(config);
+    if !graphs.is_empty() {
+        query = query.pattern(&sparql::graph_pattern("?s", graphs));
+    }
+
+    query = query
+        .pattern(concat!("?s nie:isStoredAs/nie:dataSource/tracker:available",
+                         " | nie:dataSource/tracker:available ?available ."));
+
+    if !config.show_offline {
+        query = query.filter("?available");
+    }
+
+    query = query
+        .pattern("?s nie:url ?uri .")
+        .optional("?s nie:title ?title .")
+        .optional(concat!("?s nao:hasTag | nie:interpretedAs/nao:hasTag ?fav .",
+                          " FILTER (?fav = nao:predefined-tag-favorite)"));
+
+    if full || detail == Detail::Count {
+        query = query
+            .optional("?s nie:mimeType | nie:interpretedAs/nie:mimeType ?mime .");
+    }
+    if full {
+        query = query
+            .optional("?s nie:byteSize | nie:isStoredAs/nie:byteSize ?size .");
+        if let Some(src) = q.snippet {
+            let terms = q.match_terms(config);
+            for p in src.patterns(terms.first().map(String::as_str)) {
+                query = query.pattern(&p);
+            }
+        }
+    }
+
+    query = q.restrict(config, query);
+
+    /* name: tokens need a file name; type: tokens may match by mimetype
+     * instead */
+    let extensions = q.types.iter().any(|t| matches!(t, FileType::Extension(_)));
+    if !q.names.is_empty() {
+        query = query.pattern("?s nfo:fileName ?fname .");
+    } else if extensions {
+        query = query.optional("?s nfo:fileName ?fname .");
+    }
+
+    for n in &q.names {
+        query = query.filter(&name_cond("?fname", n, config));
+    }
+
+    /* a separate mimetype variable, as ?mime isn't bound in fast queries */
+    if !q.types.is_empty() {
+        query = query
+            .optional("?s nie:mimeType | nie:interpretedAs/nie:mimeType ?tmime .")
+            .pattern(r#"BIND (COALESCE(?tmime, "") AS ?ftype)"#);
+        let conds = q.types
+            .iter()
+            .map(|t| t.cond("?ftype", r#"COALESCE(?fname, "")"#))
+            .collect::<Vec<_>>()
+            .join(" || ");
+        query = query.filter(&conds);
+    }
+
+    for p in &q.paths {
+        query = query.filter(&path_cond(p, config));
+    }
+
+    if q.here {
+        let root = project::root(config)
+            .and_then(|r| Url::from_directory_path(r).ok())
+            .context("no current project")?;
+        query = query.filter(&format!("STRSTARTS(?uri, {})",
+                                      sparql::literal(root.as_str())));
+    }
+
+    /* a separate mimetype variable, as ?mime isn't bound in fast queries */
+    if let Some(f) = mimetype_filter(config, "?xmime") {
+        query = query
+            .optional("?s nie:mimeType | nie:interpretedAs/nie:mimeType ?xmime .")
+            .filter(&f);
+    }
+
+    if detail == Detail::Count {
+        return query.group_by("?mime").build();
+    }
+
+    /* favorites rank higher, by a configurable amount */
+    let boost = match config.favorite_boost {
+        0 => None,
+        b => Some(format!("IF(BOUND(?fav), {}, 0)", b)),
+    };
 
-    let query =
-            format!(r#"SELECT DISTINCT ?s ?uri ?title fts:snippet(?s, "", "")
-                WHERE {{
-                    ?s fts:match "{}" .
-                    ?s nie:isStoredAs/nie:dataSource/tracker:available
-                        | nie:dataSource/tracker:available true
-                    .
-                    ?s nie:url ?uri .
-                    OPTIONAL {{ ?s nie:title ?title . }}
-                }}
-                OFFSET 0 LIMIT 15"#, sparql_escape(q));
+    let ordered = match (q.score(config), boost) {
+        (Some(score), Some(boost)) => {
+            query = query.order_desc(&format!("{} + {}", score, boost));
+            true
+        }
+        (Some(score), None) => {
+            query = query.order_desc(&score);
+            true
+        }
+        (None, Some(boost)) if fts => {
+            query = query.order_desc(&boost);
+            true
+        }
+        (None, _) => false,
+    };
 
-    let msg = Message::new_method_call("org.freedesktop.Tracker3.Miner.Files",
-            "/org/freedesktop/Tracker3/Endpoint",
-            "org.freedesktop.Tracker3.Endpoint",
-            "Query")
-        .unwrap()
-        .append1(query)
-        .append1(pipe.writer)
-        .append1(args);
+    /* with fts, ranking breaks ties */
+    if ordered && fts {
+        query = query.order_desc("fts:rank(?s)");
+    }
 
-    let reply = conn.channel().send_with_reply_and_block(msg, DBUS_TIMEOUT)?;
+    query.offset(offset).limit(limit).build()
+}
 
-    /* ensure we have four columns */
-    let res = reply.read1::<Vec<&str>>()?;
+/* run a search. A progressive search returns the results of a fast query
+ * when the full results aren't cached yet; see tracker::query_progressive */
+fn tracker_search_v3(q: &SearchQuery, config: &Config, offset: usize,
+                     limit: usize, progressive: bool)
+        -> anyhow::Result<Vec<QueryResult>> {
+    let query = search_query_v3(q, config, offset, limit, Detail::Full)?;
+    let (columns, buf) = if progressive {
+        let fast = search_query_v3(q, config, offset, limit, Detail::Fast)?;
+        tracker::query_progressive(&fast, &query)?
+    } else {
+        tracker::query_v3(&query)?
+    };
 
-    if res.len() != 4 {
+    if columns.len() != SEARCH_COLUMNS {
         return Err(anyhow!("Invalid search results"));
     }
 
-    let mut buf = Vec::new();
-    pipe.reader.read_to_end(&mut buf)?;
+    let (_, rows) = nom::multi::many0(tracker::parse_row)(buf.as_slice())
+        .map_err(|_| anyhow!("Invalid search results"))?;
 
-    let (_, res) = nom::multi::many0(parse_one)(buf.as_slice()).unwrap();
+    let res = rows
+        .iter()
+        .filter_map(|r| {
+            let r = r.iter().map(|c| c.as_ref()).collect::<Vec<_>>();
+            QueryResult::from_row(&r)
+        })
+        .collect();
 
     Ok(res)
 }
 
+/* the number of results in each category, in the usual category order.
+ * These count everything the query matches, including hidden and ignored
+ * files, so are an upper bound on what a search would list */
+fn category_counts(q: &SearchQuery, config: &Config)
+        -> anyhow::Result<Vec<(Category, usize)>> {
+    let query = search_query_v3(q, config, 0, 0, Detail::Count)?;
+    let (columns, buf) = tracker::query_v3(&query)?;
+    if columns.len() != 2 {
+        return Err(anyhow!("Invalid count results"));
+    }
+
+    let (_, rows) = nom::multi::many0(tracker::parse_row)(buf.as_slice())
+        .map_err(|_| anyhow!("Invalid count results"))?;
+
+    let mut counts = HashMap::new();
+    for r in &rows {
+        if let [mime, n] = r.as_slice() {
+            let n = n.parse::<usize>().unwrap_or(0);
+            *counts.entry(Category::from_mimetype(mime)).or_insert(0) += n;
+        }
+    }
+
+    Ok(Category::ALL
+        .iter()
+        .filter_map(|c| counts.get(c).map(|n| (*c, *n)))
+        .filter(|(_, n)| *n > 0)
+        .collect())
+}
+
 fn tracker_query_uuid_v3(uuid: &str) -> anyhow::Result<String> {
-    let conn = Connection::new_session()?;
-    let mut pipe = Pipe::new()?;
-    let args : HashMap<&str,Variant<u32>> = HashMap::new();
-
-    let msg = Message::new_method_call("org.freedesktop.Tracker3.Miner.Files",
-                                       "/org/freedesktop/Tracker3/Endpoint",
-                                       "org.freedesktop.Tracker3.Endpoint",
-                                       "Query")
-        .unwrap()
-        .append1(format!(r#"SELECT ?url
-                 WHERE {{
-                    "{}" nie:url ?url
-                 }}
-                 LIMIT 1"#, sparql_escape(uuid)))
-        .append1(pipe.writer)
-        .append1(args);
-
-    let reply = conn.channel().send_with_reply_and_block(msg, DBUS_TIMEOUT)?;
-
-    let res = reply.read1::<Vec<&str>>()?;
-    if res.len() != 1 {
+    let query = Select::new(&["?url"])
+        .pattern(&format!("{} nie:url ?url", sparql::literal(uuid)))
+        .limit(1)
+        .build()?;
+
+    let (columns, buf) = tracker::query_v3(&query)?;
+    if columns.len() != 1 {
         return Err(anyhow!("Invalid UUID search result"));
     }
 
-    let mut buf = Vec::new();
-    pipe.reader.read_to_end(&mut buf)?;
-    let b = buf.as_slice();
+    let (_, row) = tracker::parse_row(buf.as_slice())
+        .map_err(|_| anyhow!("No result for UUID '{}'", uuid))?;
 
-    let p = u32(nom::number::Endianness::Native);
+    match row.first() {
+        Some(uri) if !uri.is_empty() => Ok(uri.to_string()),
+        _ => Err(anyhow!("No result for UUID '{}'", uuid)),
+    }
+}
 
-    let res : nom::IResult<&[u8],(_, u32,u32)> = tuple((tag([1u8, 0, 0, 0]), p, p))(b);
-    let (b, (_, _type, len)) = res.unwrap();
-    let res : nom::IResult<&[u8],&[u8]> = nom::bytes::complete::take(len)(b);
-    let (_, x) = res.unwrap();
+/* a value for a listed result, from a state parameter of "uuid value"
+ * lines */
+fn listed_value(state: &flow::State, key: &str, uuid: &str) -> Option<String> {
+    state.get(key)?
+        .lines()
+        .filter_map(|l| l.split_once(' '))
+        .find(|(id, _)| *id == uuid)
+        .map(|(_, v)| v.to_string())
+}
 
-    let uri = std::str::from_utf8(x).unwrap();
-    Ok(uri.to_string())
+/* the uri of a result: from those listed in the state, else from a query */
+fn result_uri(state: Option<&flow::State>, uuid: &str) -> anyhow::Result<String> {
+    match state.and_then(|s| listed_value(s, "uris", uuid)) {
+        Some(uri) => Ok(uri),
+        None => tracker_query_uuid_v3(uuid),
+    }
 }
 
-fn format_rofi_option<'a, I>(val: Option<&'a str>, meta: I) -> Vec<u8>
-where
-    I: IntoIterator<Item = (&'a str, &'a str)>
-{
-    let mut v = Vec::new();
-    if let Some(valstr) = val {
-        v.extend(valstr.as_bytes())
-    }
-    v.push(0);
-    v.extend(meta.into_iter().map(|(name,val)| {
-                let mut optdata = Vec::new();
-                optdata.extend(name.as_bytes());
-                optdata.push(0x1f);
-                optdata.extend(val.as_bytes());
-                optdata })
-            .collect::<Vec<_>>()
-            .join(&0x1fu8));
-    v.push(0x0a);
-    v
+/* whether to list a result: hidden files are left out, unless enabled,
+ * and ignored files are always left out */
+fn visible(r: &QueryResult, show_hidden: bool, ignores: &Ignores) -> bool {
+    if !show_hidden && r.hidden() {
+        return false;
+    }
+    match r.uri.to_file_path() {
+        Ok(p) => !ignores.ignored(&p),
+        Err(_) => true,
+    }
 }
 
-fn escape_result(r: &str) -> String
-{
-    r.replace('\n', " ").replace('\0', "")
+/* drop results that aren't visible, and results from categories that have
+ * reached their configured quota, and limit to the total number of results.
+ * Returns the remaining results, and the number of input results consumed
+ * to produce them */
+fn apply_quotas(results: Vec<QueryResult>, config: &Config, show_hidden: bool)
+        -> (Vec<QueryResult>, usize) {
+    let ignores = Ignores::load(config);
+    let mut counts: HashMap<Category, usize> = HashMap::new();
+    let mut consumed = 0;
+
+    let results = results
+        .into_iter()
+        .inspect(|_| consumed += 1)
+        .filter(|r| visible(r, show_hidden, &ignores))
+        .filter(|r| {
+            let cat = r.category();
+            let n = counts.entry(cat).or_insert(0);
+            *n += 1;
+            config.max_per_category
+                .iter()
+                .all(|(c, max)| *c != cat || *n <= *max)
+        })
+        .take(RESULT_LIMIT)
+        .collect();
+
+    (results, consumed)
 }
 
-fn format_result(r: &QueryResult) -> Vec<u8> {
-    let opts: Vec<(&str,&str)> = vec![("info", &r.uuid)];
-    format_rofi_option(Some(&escape_result(&r.description())), opts)
+/* find results that may be duplicates: those with the same size, and the
+ * same title (or file name, for results without a title). Duplicates are
+ * marked, and returned in groups of uuids */
+fn mark_duplicates(results: &mut [QueryResult]) -> Vec<Vec<String>> {
+    let key = |r: &QueryResult| {
+        let size = r.size.filter(|s| *s > 0)?;
+        let title = Some(r.title.clone())
+            .filter(|t| !t.is_empty())
+            .or_else(|| r.file_name())?;
+        Some((size, title))
+    };
+
+    let mut groups: Vec<((u64, String), Vec<usize>)> = Vec::new();
+    for (i, r) in results.iter().enumerate() {
+        if let Some(k) = key(r) {
+            match groups.iter_mut().find(|(g, _)| *g == k) {
+                Some((_, members)) => members.push(i),
+                None => groups.push((k, vec![i])),
+            }
+        }
+    }
+
+    groups
+        .into_iter()
+        .filter(|(_, members)| members.len() > 1)
+        .map(|(_, members)| members
+            .into_iter()
+            .map(|i| {
+                results[i].duplicate = true;
+                results[i].uuid.clone()
+            })
+            .collect())
+        .collect()
 }
 
-fn main() -> anyhow::Result<()> {
-    let args: Vec<String> = env::args().collect();
+/* reorder results by the configured extension preferences, keeping
+ * Tracker's ranking among results with the same preference. This is done
+ * after quotas, so only reorders the results on a page, and paging isn't
+ * affected */
+fn prefer_extensions(results: &mut [QueryResult], config: &Config) {
+    if config.extensions.is_empty() {
+        return;
+    }
 
-    /* no args: initial run */
-    if args.len() == 1 {
-        return Ok(());
+    results.sort_by_key(|r| {
+        let pref = r.extension().and_then(|e| config.extensions
+            .iter()
+            .find(|(x, _)| *x == e)
+            .map(|(_, p)| *p));
+        Reverse(pref.unwrap_or(0))
+    });
+}
+
+fn format_result(r: &QueryResult, config: &Config,
+                 recent: &HashSet<String>) -> Row {
+    let mut row = Row::new(&r.description());
+    /* rofi matches and sorts on the plain description, but shows it with
+     * any decorations */
+    if config.rofi_sort {
+        row.display = Some(row.text.clone());
+    }
+    if let Some(b) = config.badge(&r.mimetype) {
+        row.prefix(b);
+    }
+    row.info = Some(r.uuid.clone());
+    row.icon = config.icon(&r.mimetype);
+    if r.duplicate {
+        row.meta = Some("possible duplicate".to_string());
     }
 
-    /* if we have an info string, lookup a uuid and open */
-    if let Ok(uuid) = env::var("ROFI_INFO") {
-        let uri = tracker_query_uuid_v3(&uuid)
-            .with_context(|| format!("can't lookup UUID '{}'", uuid))?;
-        return match daemon(false, false) {
-            Err(_) => Err(anyhow!("can't fork")),
-            Ok(Fork::Child) => opener::open(uri).context("can't open file"),
-            Ok(Fork::Parent(_)) => Ok(()),
+    for state in r.states(recent) {
+        if let Some((_, style)) = config.styles.iter().find(|(s, _)| *s == state) {
+            row.apply_style(style);
         }
     }
 
-    /* otherwise, search and return results */
-    let query = args[1..].join(" ");
+    row
+}
 
-    let stdout = io::stdout();
-    let mut fd = stdout.lock();
+/* number of rows that can be quick-selected */
+const QUICK_SELECT_MAX: usize = 9;
 
-    let results = tracker_search_v3(&query)
-        .with_context(|| format!("failed search for \"{}\"", query))?;
+/* split a trailing quick-select suffix ("!N") from a query */
+fn split_quick_select(query: &str) -> (&str, Option<usize>) {
+    if let Some((q, n)) = query.trim_end().rsplit_once(" !") {
+        if let Ok(n) = n.parse::<usize>() {
+            if (1..=QUICK_SELECT_MAX).contains(&n) {
+                return (q, Some(n));
+            }
+        }
+    }
+    (query, None)
+}
 
-    if results.len() == 0 {
-        let opt = format_rofi_option(Some("no results"),
-                    vec![("nonselectable", "true")]);
-        fd.write_all(&opt).context("write")
-    } else {
-        results
-            .into_iter()
-            .map(|r| format_result(&r))
-            .map(|s| fd.write_all(&s))
-            .fold(anyhow::Result::Ok(()),
-                |s,r| { s.and(r.context("write")) })
+/* a list of result uuids in a state parameter: "selected" for the
+ * selection, or "pinned" for pinned results */
+fn uuid_list<'a>(state: &'a flow::State, key: &str) -> Vec<&'a str> {
+    state.get(key)
+        .map(|s| s.split_whitespace().collect())
+        .unwrap_or_default()
+}
+
+fn selected(state: &flow::State) -> Vec<&str> {
+    uuid_list(state, "selected")
+}
+
+/* add or remove a result from one of the state's uuid lists */
+fn toggle_listed(mut state: flow::State, key: &str, uuid: &str) -> flow::State {
+    let mut list: Vec<String> = uuid_list(&state, key)
+        .into_iter()
+        .map(str::to_string)
+        .collect();
+
+    match list.iter().position(|s| s == uuid) {
+        Some(i) => { list.remove(i); }
+        None => list.push(uuid.to_string()),
+    }
+
+    state.set(key, &list.join(" "));
+    state
+}
+
+/* number of results to query for a page; we need more than we show if
+ * quotas may drop some */
+fn result_limit(config: &Config) -> usize {
+    if config.max_per_category.is_empty() {
+        RESULT_LIMIT
+    } else {
+        RESULT_LIMIT * QUOTA_QUERY_FACTOR
+    }
+}
+
+/* rofi only passes kb-custom keys to the script once it asks for them, so
+ * views of results ask when there are actions bound */
+fn write_hot_keys<W: Write>(out: &mut W, config: &Config) -> anyhow::Result<()> {
+    if config.keys.is_empty() {
+        return Ok(());
+    }
+    out.write_all(&rofi::mode_option(Key::UseHotKeys, "true")).context("write")
+}
+
+/* search contacts or events, for a :contacts or :events command */
+fn pim_search<W: Write>(out: &mut W, config: &Config, state: &flow::State,
+                        kind: pim::Kind, text: &str) -> anyhow::Result<()> {
+    out.write_all(&state.data_option()).context("write")?;
+
+    let service = match pim::find_endpoint(kind, config) {
+        Some(s) => s,
+        None => {
+            let mut row = Row::new(&format!("no {} available", kind.name()));
+            row.nonselectable = true;
+            return out.write_all(&row.format(false)).context("write");
+        }
+    };
+
+    let items = pim::search(&service, kind, text)
+        .with_context(|| format!("failed {} search for \"{}\"", kind.name(), text))?;
+
+    let mut rows = items
+        .iter()
+        .map(|i| flow::State::new("open-pim")
+            .with("kind", kind.name())
+            .with("id", &i.id)
+            .transition_row(&i.text))
+        .collect::<Vec<_>>();
+
+    if rows.is_empty() {
+        let mut row = Row::new("no results");
+        row.nonselectable = true;
+        rows.push(row);
+    }
+
+    rows.iter()
+        .try_for_each(|r| out.write_all(&r.format(false)).context("write"))
+}
+
+/* whether to apply types hinted at by words in a state's query */
+fn auto_type(config: &Config, state: &flow::State) -> bool {
+    config.auto_type && state.get("autotype") != Some("off")
+}
+
+/* parse a query, applying a type hinted at by its words when auto_type is
+ * set. Returns the hint too, when a type was applied. Searches, and the
+ * full result sets exported from them, share this so they list the same
+ * results */
+fn parse_search(query: &str, auto_type: bool) -> (SearchQuery, Option<String>) {
+    let mut q = SearchQuery::parse(query);
+    let hint = if auto_type { q.detect_type() } else { None };
+    (q, hint)
+}
+
+fn search<W: Write>(out: &mut W, config: &Config, state: &flow::State)
+        -> anyhow::Result<()> {
+    let query = state.get("query").unwrap_or_default();
+
+    let (query, quick_select) = if config.quick_select {
+        split_quick_select(query)
+    } else {
+        (query, None)
+    };
+
+    if config.restore_query && !query.trim().is_empty() {
+        let _ = history::record_query(query);
+    }
+
+    if let Some((kind, text)) = pim::Kind::parse_command(query) {
+        return pim_search(out, config, state, kind, text);
+    }
+
+    let offset = state.get("offset")
+        .and_then(|o| o.parse().ok())
+        .unwrap_or(0);
+
+    let limit = result_limit(config);
+    let (q, hint) = parse_search(query, auto_type(config, state));
+
+    if q.here && project::root(config).is_none() {
+        out.write_all(&state.data_option()).context("write")?;
+        let mut row = Row::new(&format!("no current project for :here; set {} or \
+                                         the project option", project::PROJECT_ENV));
+        row.nonselectable = true;
+        return out.write_all(&row.format(false)).context("write");
+    }
+
+    let results = tracker_search_v3(&q, config, offset, limit, config.progressive)
+        .with_context(|| format!("failed search for \"{}\"", query))?;
+    let fetched = results.len();
+    let (mut results, consumed) = apply_quotas(results, config, q.show_hidden(config));
+    prefer_extensions(&mut results, config);
+    let duplicates = mark_duplicates(&mut results);
+
+    /* pinned results stay at the top of the first page, whether or not
+     * they match the current query */
+    let pinned = if offset == 0 {
+        uuid_list(state, "pinned")
+            .into_iter()
+            .filter_map(|uuid| match results.iter().position(|r| r.uuid == uuid) {
+                Some(i) => Some(results.remove(i)),
+                None => tracker_query_uuid_v3(uuid)
+                    .ok()
+                    .and_then(|uri| QueryResult::from_uri(uuid, &uri)),
+            })
+            .collect::<Vec<_>>()
+    } else {
+        Vec::new()
+    };
+
+    if let Some(n) = quick_select {
+        if let Some(r) = results.get(n - 1) {
+            return action::open_uri(&r.raw_uri, config);
+        }
+    }
+
+    /* keep the uris of the listed results, so opening one doesn't need
+     * another query */
+    let uris = pinned
+        .iter()
+        .chain(results.iter())
+        .map(|r| format!("{} {}", r.uuid, r.raw_uri))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    /* and their snippets, for the copy-snippet action */
+    let snippets = pinned
+        .iter()
+        .chain(results.iter())
+        .filter(|r| !r.snippet.is_empty())
+        .map(|r| format!("{} {}", r.uuid, r.snippet.replace('\n', " ")))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut state = state.clone()
+        .with("query", query)
+        .with("uris", &uris)
+        .with("snippets", &snippets);
+
+    /* drop pins for files that are no longer in the index */
+    if offset == 0 && state.get("pinned").is_some() {
+        let ids = pinned.iter().map(|r| r.uuid.as_str()).collect::<Vec<_>>();
+        state.set("pinned", &ids.join(" "));
+    }
+    out.write_all(&state.data_option()).context("write")?;
+    write_hot_keys(out, config)?;
+
+    /* the listed results are only needed in the data option; transition
+     * rows get them from ROFI_DATA */
+    let state = state.without("uris").without("snippets");
+
+    let markup = uses_markup(config);
+    if markup {
+        out.write_all(&rofi::mode_option(Key::MarkupRows, "true")).context("write")?;
+    }
+
+    let mut rows = Vec::new();
+
+    if offset == 0 {
+        if let Some(path) = typed_path(query) {
+            let open = flow::State::new("open-path")
+                .with("path", &path.to_string_lossy());
+            rows.push(open.transition_row(&format!("Open this path: {}", path.display())));
+        }
+
+        if let (Some(hint), Some(ty)) = (&hint, q.types.first()) {
+            let name = match ty {
+                FileType::Category(c) => c.name().to_string(),
+                FileType::Extension(e) => format!("{} files", e),
+            };
+            let all = state.clone().with("autotype", "off");
+            rows.push(all.transition_row(&format!(
+                "Showing {}, from \"{}\"; select to search all files", name, hint)));
+        }
+
+        for s in suggestions(query) {
+            let search = state.clone()
+                .with("query", &s)
+                .with("offset", "0");
+            rows.push(search.transition_row(&format!("Search for: {}", s)));
+        }
+
+        /* filters are only worth offering if results span categories */
+        if config.category_rows && q.types.is_empty() && !results.is_empty() {
+            let counts = category_counts(&q, config).unwrap_or_default();
+            if counts.len() > 1 {
+                for (c, n) in counts {
+                    let filtered = state.clone()
+                        .with("query", &format!("{} type:{}", query, c.name()))
+                        .with("offset", "0");
+                    rows.push(filtered.transition_row(&format!("{} ({})", c.label(), n)));
+                }
+            }
+        }
+    }
+
+    let sel = selected(&state);
+    if !sel.is_empty() {
+        let open = flow::State::new("open-selected")
+            .with("selected", &sel.join(" "));
+        rows.push(open.transition_row(&format!("Open {} selected files", sel.len())));
+    }
+
+    let recent = history::recently_opened();
+
+    rows.extend(pinned
+        .iter()
+        .map(|r| {
+            let mut row = format_result(r, config, &recent);
+            row.prefix("📌");
+            row
+        }));
+
+    if config.spelling && offset == 0 && !results.is_empty() {
+        let _ = history::record_search(query);
+    }
+
+    if results.is_empty() {
+        let mut row = Row::new("no results");
+        row.nonselectable = true;
+        rows.push(row);
+
+        if config.spelling && offset == 0 {
+            let is_term = |w: &str| !FTS_OPERATORS.contains(&w);
+            for c in spell::corrections(query, is_term) {
+                let search = state.clone()
+                    .with("query", &c)
+                    .with("offset", "0");
+                rows.push(search.transition_row(&format!("Did you mean \"{}\"?", c)));
+            }
+        }
+    } else {
+        /* "!N" applies to a new query, so always picks from the first page;
+         * only number the rows there */
+        let numbered = if config.quick_select && offset == 0 { QUICK_SELECT_MAX } else { 0 };
+        rows.extend(results
+            .iter()
+            .map(|r| format_result(r, config, &recent))
+            .enumerate()
+            .map(|(i, mut r)| {
+                if i < numbered {
+                    r.prefix(&format!("{}.", i + 1));
+                }
+                if matches!(r.info.as_deref(), Some(u) if sel.contains(&u)) {
+                    r.prefix("✓");
+                }
+                r
+            }));
+    }
+
+    /* duplicates open side by side, in a single instance of their
+     * application, where it supports that */
+    for group in &duplicates {
+        let name = results
+            .iter()
+            .find(|r| r.uuid == group[0])
+            .and_then(QueryResult::file_name)
+            .unwrap_or_default();
+        let open = flow::State::new("open-selected")
+            .with("selected", &group.join(" "));
+        rows.push(open.transition_row(&format!("Compare {} possible duplicates of {}",
+                                               group.len(), name)));
+    }
+
+    /* offer a playlist when there's some music in the results */
+    let tracks = results.iter().filter(|r| r.category() == Category::Audio).count();
+    if tracks > 1 {
+        let mut play = flow::State::new("playlist").with("query", query);
+        if let Some(a) = state.get("autotype") {
+            play.set("autotype", a);
+        }
+        rows.push(play.transition_row("Play audio results as a playlist"));
+    }
+
+    /* a full set of results means there may be more available */
+    if fetched == limit || consumed < fetched {
+        let next = (offset + consumed).to_string();
+        rows.push(state.clone().with("offset", &next).transition_row("More results…"));
+    }
+
+    rows.iter()
+        .try_for_each(|r| out.write_all(&r.format(markup)).context("write"))
+}
+
+/* the query as the path of an existing file, if it's an absolute (or
+ * home-relative) path */
+fn typed_path(query: &str) -> Option<PathBuf> {
+    let q = query.trim();
+    if !(q.starts_with('/') || q.starts_with("~/")) {
+        return None;
+    }
+    let path = config::expand_path(q);
+    if path.exists() { Some(path) } else { None }
+}
+
+/* whether result rows use pango markup, which colors need. The
+ * markup-rows option applies to every row of the output, so all rows need
+ * escaping when it's set */
+fn uses_markup(config: &Config) -> bool {
+    config.styles.iter().any(|(_, s)| s.color.is_some())
+}
+
+/* rows for the initial run, before any query is entered */
+fn initial_rows<W: Write>(out: &mut W, config: &Config) -> anyhow::Result<()> {
+    let last = if config.restore_query { history::last_query() } else { None };
+
+    /* the recent files set markup-rows */
+    let markup = config.recent_files && uses_markup(config);
+
+    if let Some(query) = last {
+        let repeat = flow::State::new("search").with("query", &query);
+        let row = repeat.transition_row(&format!("Repeat last search: {}", query));
+        out.write_all(&row.format(markup)).context("write")?;
+    }
+
+    if config.recent_files {
+        recent_rows(out, config)?;
+    }
+    Ok(())
+}
+
+/* the most recently modified files, as search results */
+fn recent_files(config: &Config) -> anyhow::Result<Vec<QueryResult>> {
+    let mut query = Select::new(&["?s", "?uri", "?title", r#"("" AS ?snippet)"#,
+                                  "?mime", "?available", "?fav", "?size"])
+        .distinct()
+        .pattern("?s a nfo:FileDataObject ; nfo:fileLastModified ?mtime ; nie:url ?uri .")
+        .pattern("?s nie:dataSource/tracker:available ?available .");
+
+    if !config.show_offline {
+        query = query.filter("?available");
+    }
+
+    query = query
+        .optional("?s nie:interpretedAs/nie:title ?title .")
+        .optional(concat!("?s nie:interpretedAs/nao:hasTag ?fav .",
+                          " FILTER (?fav = nao:predefined-tag-favorite)"))
+        .optional("?s nie:interpretedAs/nie:mimeType ?mime .")
+        .optional("?s nie:byteSize ?size .")
+        .filter(r#"!BOUND(?mime) || ?mime != "inode/directory""#);
+
+    if let Some(f) = mimetype_filter(config, "?mime") {
+        query = query.filter(&f);
+    }
+
+    let query = query
+        .order_desc("?mtime")
+        .limit(result_limit(config))
+        .build()?;
+
+    let (columns, buf) = tracker::query_v3(&query)?;
+    if columns.len() != SEARCH_COLUMNS {
+        return Err(anyhow!("Invalid recent file results"));
+    }
+
+    let (_, rows) = nom::multi::many0(tracker::parse_row)(buf.as_slice())
+        .map_err(|_| anyhow!("Invalid recent file results"))?;
+
+    Ok(rows
+        .iter()
+        .filter_map(|r| {
+            let r = r.iter().map(|c| c.as_ref()).collect::<Vec<_>>();
+            QueryResult::from_row(&r)
+        })
+        .collect())
+}
+
+/* recently modified files, under headers for when they were modified */
+fn recent_rows<W: Write>(out: &mut W, config: &Config) -> anyhow::Result<()> {
+    let results = recent_files(config).context("failed recent files query")?;
+    let (results, _) = apply_quotas(results, config, config.show_hidden);
+
+    /* the index may lag behind the files, so group (and order) by their
+     * current mtime, where we have one */
+    let mut results = results
+        .into_iter()
+        .map(|r| {
+            let mtime = r.uri
+                .to_file_path()
+                .ok()
+                .and_then(|p| fs::metadata(p).ok())
+                .and_then(|m| m.modified().ok())
+                .unwrap_or(UNIX_EPOCH);
+            (mtime, r)
+        })
+        .collect::<Vec<_>>();
+    results.sort_by_key(|(t, _)| Reverse(*t));
+
+    let uris = results
+        .iter()
+        .map(|(_, r)| format!("{} {}", r.uuid, r.raw_uri))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let state = flow::State::new("recent").with("uris", &uris);
+    out.write_all(&state.data_option()).context("write")?;
+    write_hot_keys(out, config)?;
+
+    let markup = uses_markup(config);
+    if markup {
+        out.write_all(&rofi::mode_option(Key::MarkupRows, "true")).context("write")?;
+    }
+
+    let recent = history::recently_opened();
+    let now = SystemTime::now();
+    let mut period = None;
+
+    for (mtime, r) in &results {
+        let p = Period::of(*mtime, now);
+        if period != Some(p) {
+            let mut header = Row::new(p.label());
+            header.nonselectable = true;
+            out.write_all(&header.format(markup)).context("write")?;
+            period = Some(p);
+        }
+        out.write_all(&format_result(r, config, &recent).format(markup))
+            .context("write")?;
+    }
+    Ok(())
+}
+
+/* prompt for a tag to add to a file */
+fn tag_prompt<W: Write>(out: &mut W, state: &flow::State) -> anyhow::Result<()> {
+    let uri = state.get("uri").context("no file to tag")?;
+    let tags = action::tags(uri);
+    let message = if tags.is_empty() {
+        String::from("Add a tag; no current tags")
+    } else {
+        format!("Add a tag; current tags: {}", tags.join(", "))
+    };
+
+    out.write_all(&state.data_option()).context("write")?;
+    out.write_all(&rofi::mode_option(Key::Prompt, "tag")).context("write")?;
+    out.write_all(&rofi::mode_option(Key::Message, &message)).context("write")
+}
+
+/* list the applications that can open a file's type, to choose a new
+ * default from */
+fn default_app_prompt<W: Write>(out: &mut W, state: &flow::State)
+        -> anyhow::Result<()> {
+    let uri = state.get("uri").context("no file")?;
+    let path = action::file_path(uri)?;
+    let mimetype = launcher::mimetype(&path)
+        .with_context(|| format!("unknown file type for {}", path.display()))?;
+
+    let files = match path.extension() {
+        Some(e) => format!(".{} files", e.to_string_lossy()),
+        None => format!("{} files", mimetype),
+    };
+
+    let current = launcher::default_for(&mimetype);
+    let mut rows = launcher::handlers(&mimetype)
+        .into_iter()
+        .map(|h| {
+            let is_current = current.as_deref() == Some(h.id.as_str());
+            let text = if is_current {
+                format!("{} (current default)", h.name)
+            } else {
+                h.name.clone()
+            };
+            let mut row = flow::State::new("set-default")
+                .with("uri", uri)
+                .with("mimetype", &mimetype)
+                .with("id", &h.id)
+                .transition_row(&text);
+            row.active = is_current;
+            row
+        })
+        .collect::<Vec<_>>();
+
+    if rows.is_empty() {
+        let mut row = Row::new(&format!("no applications handle {}", mimetype));
+        row.nonselectable = true;
+        rows.push(row);
+    }
+
+    out.write_all(&state.data_option()).context("write")?;
+    out.write_all(&rofi::mode_option(Key::Prompt, "default app")).context("write")?;
+    out.write_all(&rofi::mode_option(Key::Message,
+                                     &format!("Always open {} with…", files)))
+        .context("write")?;
+    rows.iter()
+        .try_for_each(|r| out.write_all(&r.format(false)).context("write"))
+}
+
+/* steps that take typed input, rather than starting a new search */
+fn takes_input(state: &flow::State) -> bool {
+    matches!(state.step.as_str(), "search" | "tag")
+}
+
+fn run_step<W: Write>(out: &mut W, config: &Config, state: &flow::State,
+                      input: Option<&str>) -> anyhow::Result<()> {
+    match state.step.as_str() {
+        "recent" => initial_rows(out, config),
+        "search" => {
+            let mut state = state.clone();
+            if let Some(query) = input {
+                state.set("query", query);
+                state.set("offset", "0");
+                /* a new query gets automatic types again */
+                if state.get("autotype").is_some() {
+                    state.set("autotype", "on");
+                }
+            }
+            search(out, config, &state)
+        }
+        "open-selected" => {
+            let listed = flow::State::from_env();
+            let uris = selected(state)
+                .into_iter()
+                .map(|uuid| result_uri(listed.as_ref(), uuid))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            launcher::open_grouped(&uris, config)
+        }
+        "tag" => match input {
+            Some(tag) => {
+                let uri = state.get("uri").context("no file to tag")?;
+                action::add_tag(uri, tag)
+            }
+            None => tag_prompt(out, state),
+        },
+        "open-path" => {
+            let path = state.get("path").context("no path")?;
+            let uri = Url::from_file_path(path)
+                .map_err(|_| anyhow!("invalid path '{}'", path))?;
+            action::open_uri(uri.as_str(), config)
+        }
+        "default-app" => default_app_prompt(out, state),
+        "set-default" => {
+            let uri = state.get("uri").context("no file")?;
+            let mimetype = state.get("mimetype").context("no file type")?;
+            launcher::set_default(state.get("id").context("no application")?,
+                                  mimetype)?;
+            action::open_uri(uri, config)
+        }
+        "open-pim" => {
+            let kind = state.get("kind")
+                .and_then(pim::Kind::from_name)
+                .context("invalid item kind")?;
+            kind.open(state.get("id").context("no item")?)
+        }
+        "playlist" => {
+            let query = state.get("query").unwrap_or_default();
+            let results = search_all(config, query, auto_type(config, state))?;
+            let tracks = results
+                .iter()
+                .filter(|r| r.category() == Category::Audio)
+                .map(|r| (Some(r.title.as_str()).filter(|t| !t.is_empty()), &r.uri))
+                .collect::<Vec<_>>();
+            let path = playlist::write(&tracks)?;
+            let uri = Url::from_file_path(&path)
+                .map_err(|_| anyhow!("invalid playlist path"))?;
+            action::open_uri(uri.as_str(), config)
+        }
+        _ => Err(anyhow!("unknown step '{}'", state.step)),
+    }
+}
+
+/* the full result set for a query, up to EXPORT_LIMIT, without paging or
+ * quotas */
+fn search_all(config: &Config, query: &str, auto_type: bool)
+        -> anyhow::Result<Vec<QueryResult>> {
+    let (q, _) = parse_search(query, auto_type);
+    let show_hidden = q.show_hidden(config);
+    let ignores = Ignores::load(config);
+    let results = tracker_search_v3(&q, config, 0, EXPORT_LIMIT, false)
+        .with_context(|| format!("failed search for \"{}\"", query))?;
+
+    let mut results = results
+        .into_iter()
+        .filter(|r| visible(r, show_hidden, &ignores))
+        .collect::<Vec<_>>();
+    prefer_extensions(&mut results, config);
+
+    Ok(results)
+}
+
+/* write the full result set for a query, as paths (or URIs, for non-local
+ * files), one per line */
+fn export_results<W: Write>(out: &mut W, config: &Config, query: &str,
+                            auto_type: bool, format: ExportFormat)
+        -> anyhow::Result<()> {
+    for r in search_all(config, query, auto_type)? {
+        let path = match format {
+            ExportFormat::Paths => r.uri.to_file_path().ok(),
+            ExportFormat::Uris => None,
+        };
+        /* paths are written as-is, even if they're not UTF-8 */
+        match path {
+            Some(p) => out.write_all(p.as_os_str().as_bytes())?,
+            None => out.write_all(r.raw_uri.as_bytes())?,
+        }
+        out.write_all(b"\n")?;
+    }
+
+    Ok(())
+}
+
+/* write the full result set for a query as rofi -dmenu input: rows of the
+ * result description and its path (or URI), separated by a tab, with the
+ * result's icon. Rofi can show just the description, and output the
+ * selected rows for the next stage of a pipeline to take the paths from.
+ * Results with tabs or newlines in their paths would break the format, so
+ * are left out */
+fn chain_results<W: Write>(out: &mut W, config: &Config, query: &str)
+        -> anyhow::Result<()> {
+    for r in search_all(config, query, config.auto_type)? {
+        let path = match r.uri.to_file_path() {
+            Ok(p) => p.into_os_string().into_vec(),
+            Err(_) => r.raw_uri.clone().into_bytes(),
+        };
+        if path.iter().any(|b| matches!(b, b'\t' | b'\n')) {
+            continue;
+        }
+
+        let mut text = r.description().replace('\t', " ").into_bytes();
+        text.push(b'\t');
+        text.extend(path);
+
+        let mut line = Line::row_bytes(&text);
+        if let Some(icon) = config.icon(&r.mimetype) {
+            line = line.option(Key::Icon, &icon);
+        }
+        out.write_all(&line.finish())?;
+    }
+
+    Ok(())
+}
+
+fn export_to_file(config: &Config, query: &str, auto_type: bool, file: &Path,
+                  format: ExportFormat) -> anyhow::Result<()> {
+    let mut f = File::create(file)
+        .with_context(|| format!("can't create {}", file.display()))?;
+    export_results(&mut f, config, query, auto_type, format)
+}
+
+/* report problems in the config file, and with the services it refers to */
+fn check_config<W: Write>(out: &mut W) -> anyhow::Result<()> {
+    let path = Config::path().context("no config directory")?;
+    let text = match fs::read_to_string(&path) {
+        Ok(t) => t,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            writeln!(out, "{}: not found, using defaults", path.display())?;
+            String::new()
+        }
+        Err(e) => return Err(e)
+            .with_context(|| format!("can't read {}", path.display())),
+    };
+
+    writeln!(out, "checking {}", path.display())?;
+
+    let problems = Config::check(&text);
+    for p in &problems {
+        writeln!(out, "  {}", p)?;
+    }
+
+    match problems.len() {
+        0 => Ok(()),
+        n => Err(anyhow!("{} problem{} found", n, if n == 1 { "" } else { "s" })),
+    }
+}
+
+/* command-line options, which precede the query */
+#[derive(Debug, Default)]
+struct Options {
+    daemon: bool,
+    check_config: bool,
+    dry_run: bool,
+    chain: bool,
+    export: Option<String>,
+    uris: bool,
+    record: Option<String>,
+}
+
+fn parse_args(args: &[String]) -> anyhow::Result<(Options, &[String])> {
+    let mut opts = Options::default();
+    let mut i = 0;
+
+    while i < args.len() {
+        match args[i].as_str() {
+            "--daemon" => opts.daemon = true,
+            "--check-config" => opts.check_config = true,
+            "--dry-run" => opts.dry_run = true,
+            "--chain" => opts.chain = true,
+            "--uris" => opts.uris = true,
+            "--export" => {
+                i += 1;
+                let f = args.get(i).context("--export requires a file")?;
+                opts.export = Some(f.clone());
+            }
+            "--record" => {
+                i += 1;
+                let d = args.get(i).context("--record requires a directory")?;
+                opts.record = Some(d.clone());
+            }
+            _ => break,
+        }
+        i += 1;
+    }
+
+    Ok((opts, &args[i..]))
+}
+
+/* rofi (which sets ROFI_RETV) appends the user's input, or the text of the
+ * selected row, as the last argument, except on the first call. That is
+ * never parsed for options, so typing "--daemon" just searches for it;
+ * only the arguments from the mode's command line are options */
+fn parse_rofi_args<'a>(args: &'a [String], retv: &str)
+        -> anyhow::Result<(Options, &'a [String])> {
+    let input = if retv == "0" { 0 } else { 1 };
+    let split = args.len().saturating_sub(input);
+
+    let (opts, rest) = parse_args(&args[..split])?;
+    if let Some(a) = rest.first() {
+        return Err(anyhow!("unexpected argument '{}' in the rofi mode", a));
+    }
+    Ok((opts, &args[split..]))
+}
+
+fn run() -> anyhow::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    let (opts, args) = match env::var("ROFI_RETV") {
+        Ok(retv) => parse_rofi_args(&args[1..], &retv)?,
+        Err(_) => parse_args(&args[1..])?,
+    };
+
+    if opts.daemon {
+        return cache::run_daemon();
+    }
+
+    if let Some(dir) = &opts.record {
+        trace::start(Path::new(dir))
+            .with_context(|| format!("can't record to {}", dir))?;
+    }
+
+    let stdout = io::stdout();
+    let mut fd = trace::Recorded(stdout.lock());
+
+    if opts.check_config {
+        return check_config(&mut fd);
+    }
+
+    let config = Config::load()?;
+
+    /* print the search query, rather than running it. All values are
+     * inlined into the query text, so this is exactly what tracker sees */
+    if opts.dry_run {
+        let query = args.join(" ");
+        let q = SearchQuery::parse(&query);
+        let limit = if opts.export.is_some() || opts.chain {
+            EXPORT_LIMIT
+        } else {
+            result_limit(&config)
+        };
+        return writeln!(fd, "{}", search_query_v3(&q, &config, 0, limit, Detail::Full)?)
+            .context("write");
+    }
+
+    if opts.chain {
+        return chain_results(&mut fd, &config, &args.join(" "));
+    }
+
+    if let Some(file) = opts.export {
+        let format = if opts.uris { ExportFormat::Uris } else { config.export_format };
+        let query = args.join(" ");
+        return match file.as_str() {
+            "-" => export_results(&mut fd, &config, &query, config.auto_type, format),
+            _ => export_to_file(&config, &query, config.auto_type, Path::new(&file),
+                                format),
+        };
+    }
+
+    /* no args: initial run */
+    if args.is_empty() {
+        return initial_rows(&mut fd, &config);
+    }
+
+    let info = env::var("ROFI_INFO").ok();
+
+    let retv = env::var("ROFI_RETV")
+        .ok()
+        .and_then(|r| r.parse().ok())
+        .unwrap_or(0);
+
+    /* a selected transition row: move to its step */
+    if let Some(state) = info.as_deref().and_then(flow::State::from_transition) {
+        return run_step(&mut fd, &config, &state, None);
+    }
+
+    /* actions on the whole result set, rather than the selected result */
+    if let Some(action::Action::Export) = action::for_retv(retv, &config.keys) {
+        let state = flow::State::from_env().unwrap_or_default();
+        let query = state.get("query").unwrap_or_default();
+        return export_to_file(&config, query, auto_type(&config, &state),
+                              &config.export_file(), config.export_format);
+    }
+
+    /* a selected result: lookup the uuid, then open, or run the action
+     * bound to a custom key */
+    if let Some(uuid) = info {
+        let uri = result_uri(flow::State::from_env().as_ref(), &uuid)
+            .with_context(|| format!("can't lookup UUID '{}'", uuid))?;
+
+        let action = action::for_retv(retv, &config.keys)
+            .unwrap_or(&action::Action::Open);
+
+        let list = match action {
+            action::Action::Select => Some("selected"),
+            action::Action::Pin => Some("pinned"),
+            _ => None,
+        };
+
+        /* selections and pins are of search results; other views (the
+         * recent files) are just shown again */
+        if let Some(key) = list {
+            let state = flow::State::from_env()
+                .unwrap_or_else(|| flow::State::new("search"));
+            let state = match state.step.as_str() {
+                "search" => toggle_listed(state, key, &uuid),
+                _ => state,
+            };
+            return run_step(&mut fd, &config, &state, None);
+        }
+
+        if *action == action::Action::CopySnippet {
+            let snippet = flow::State::from_env()
+                .and_then(|s| listed_value(&s, "snippets", &uuid))
+                .context("no snippet for this result")?;
+            return clipboard::copy(&snippet, config.clipboard);
+        }
+
+        /* content matches (those with a snippet) in text files open in the
+         * editor, at the matching line */
+        let state = flow::State::from_env().unwrap_or_default();
+        if *action == action::Action::Open &&
+                listed_value(&state, "snippets", &uuid).is_some() {
+            let q = SearchQuery::parse(state.get("query").unwrap_or_default());
+            if editor::open_at_match(&config, &uri, &q.match_terms(&config))? {
+                return Ok(());
+            }
+        }
+
+        return match action.run(&uri, &config)? {
+            Some(state) => run_step(&mut fd, &config, &state, None),
+            None => Ok(()),
+        };
+    }
+
+    /* a custom key with no result selected: redisplay the current step */
+    if action::for_retv(retv, &config.keys).is_some() {
+        return match flow::State::from_env() {
+            Some(state) => run_step(&mut fd, &config, &state, None),
+            None => Ok(()),
+        };
+    }
+
+    /* otherwise, we have typed input: for the current step if it
+     * takes input, or for a new search */
+    let input = args.join(" ");
+    let state = flow::State::from_env()
+        .filter(takes_input)
+        .unwrap_or_else(|| flow::State::new("search"));
+
+    run_step(&mut fd, &config, &state, Some(&input))
+}
+
+fn main() -> anyhow::Result<()> {
+    crash::install();
+
+    let res = run();
+    if let Err(e) = &res {
+        trace::error(e);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn restriction(query: &str) -> String {
+        let q = SearchQuery::parse(query);
+        q.restrict(&Config::default(), Select::new(&["?s"])).build().unwrap()
+    }
+
+    /* the condition for a term appearing in the title or file name */
+    fn in_title(term: &str) -> String {
+        format!(concat!(r#"(CONTAINS(LCASE(COALESCE(?m0, "")), LCASE("{0}")) || "#,
+                        r#"CONTAINS(LCASE(COALESCE(?m1, "")), LCASE("{0}")))"#), term)
+    }
+
+    #[test]
+    fn restrict_or() {
+        let q = restriction("title:foo OR bar");
+        let filter = format!("FILTER (({}) || ({}))", in_title("foo"), in_title("bar"));
+        assert!(q.lines().any(|l| l == filter), "{}", q);
+    }
+
+    #[test]
+    fn restrict_and() {
+        let q = restriction("title:foo bar");
+        let filter = format!("FILTER (({} && {}))", in_title("foo"), in_title("bar"));
+        assert!(q.lines().any(|l| l == filter), "{}", q);
+    }
+
+    #[test]
+    fn term_groups() {
+        let config = Config::default();
+        let groups = |q: &str| SearchQuery::parse(q).term_groups(&config);
+
+        assert_eq!(groups("title:foo OR bar baz"),
+                   vec![vec!["foo"], vec!["bar", "baz"]]);
+        assert_eq!(groups(r#"title:"a b" rep*"#), vec![vec!["a", "b", "rep"]]);
+        assert_eq!(groups("title:foo NOT bar"), vec![vec!["foo"]]);
+        assert_eq!(groups("title:NEAR(a b)"), vec![vec!["a", "b"]]);
+        assert_eq!(groups("title:foo AND (bar)"), vec![vec!["foo", "bar"]]);
+        assert!(groups("title:OR").is_empty());
+    }
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn rofi_input_is_not_options() {
+        let args = strings(&["--record", "/tmp/t", "--daemon"]);
+        let (opts, query) = parse_rofi_args(&args, "2").unwrap();
+        assert!(!opts.daemon);
+        assert_eq!(opts.record.as_deref(), Some("/tmp/t"));
+        assert_eq!(query, ["--daemon"]);
+
+        let (opts, query) = parse_rofi_args(&args[..2], "0").unwrap();
+        assert_eq!(opts.record.as_deref(), Some("/tmp/t"));
+        assert!(query.is_empty());
+
+        let args = strings(&["--export x"]);
+        let (opts, query) = parse_rofi_args(&args, "1").unwrap();
+        assert!(opts.export.is_none());
+        assert_eq!(query, ["--export x"]);
+    }
+
+    #[test]
+    fn non_ascii_paths() {
+        let config = Config::default();
+        let query = |q| search_query_v3(&SearchQuery::parse(q), &config, 0, 10,
+                                        Detail::Full).unwrap();
+        assert!(query("path:café").contains(
+            r#"FILTER (CONTAINS(LCASE(?uri), "caf%c3%a9"))"#));
+        assert!(query("path:Café").contains(
+            r#"FILTER (CONTAINS(?uri, "Caf%C3%A9"))"#));
+        assert!(query("path:100%").contains(
+            r#"FILTER (CONTAINS(LCASE(?uri), "100%25"))"#));
+    }
+
+    #[test]
+    fn fast_query_excludes_mimetypes() {
+        let config = Config {
+            exclude_mimetypes: vec![String::from("image/png")],
+            ..Config::default()
+        };
+        let q = SearchQuery::parse("report");
+        for detail in [Detail::Fast, Detail::Full, Detail::Count] {
+            let query = search_query_v3(&q, &config, 0, 10, detail).unwrap();
+            assert!(query.contains(
+                "OPTIONAL { ?s nie:mimeType | nie:interpretedAs/nie:mimeType ?xmime . }"),
+                "{}", query);
+            assert!(query.contains(r#"FILTER (!BOUND(?xmime) || ?xmime NOT IN ("image/png"))"#),
+                    "{}", query);
+        }
+    }
+
+    #[test]
+    fn no_restriction_without_properties() {
+        /* all-property searches are left to fts:match, by default */
+        assert!(!restriction("foo OR bar").contains("FILTER"));
     }
 }
@@ -0,0 +1,46 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Desktop notifications
+ *
+ * Files are opened from a detached child, after rofi has closed, so there's
+ * nowhere to report an error except a notification.
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use anyhow::anyhow;
+use dbus::arg::PropMap;
+use dbus::blocking::Connection;
+use dbus::Message;
+
+use crate::tracker::DBUS_TIMEOUT;
+
+const APP_NAME: &str = "tracker-rofi";
+
+/* use the server's default expiry */
+const DEFAULT_TIMEOUT: i32 = -1;
+
+pub fn notify(summary: &str, body: &str) -> anyhow::Result<()> {
+    let conn = Connection::new_session()?;
+    let msg = Message::new_method_call("org.freedesktop.Notifications",
+            "/org/freedesktop/Notifications",
+            "org.freedesktop.Notifications",
+            "Notify")
+        .map_err(|e| anyhow!(e))?
+        .append3(APP_NAME, 0u32, "dialog-error")
+        .append3(summary, body, Vec::<&str>::new())
+        .append2(PropMap::new(), DEFAULT_TIMEOUT);
+
+    conn.channel().send_with_reply_and_block(msg, DBUS_TIMEOUT)?;
+    Ok(())
+}
+
+/* a message for an opener failure: the handler's own explanation (eg.
+ * "No application is registered as handling this file"), if it gave one */
+pub fn open_error(err: &opener::OpenError) -> String {
+    if let opener::OpenError::ExitStatus { stderr, .. } = err {
+        if let Some(l) = stderr.lines().rev().map(str::trim).find(|l| !l.is_empty()) {
+            return l.to_string();
+        }
+    }
+    err.to_string()
+}
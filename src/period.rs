@@ -0,0 +1,169 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Grouping of times into periods relative to now, in local time, as file
+ * managers do for recent files
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use std::convert::TryFrom;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Period {
+    Today,
+    Yesterday,
+    ThisWeek,
+    Earlier,
+}
+
+/* the local time of t */
+fn local_time(t: SystemTime) -> Option<libc::tm> {
+    let secs = t.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    let tt = libc::time_t::try_from(secs).ok()?;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::localtime_r(&tt, &mut tm) }.is_null() {
+        return None;
+    }
+    Some(tm)
+}
+
+/* the start of the local day n days before that of tm. mktime normalises
+ * the day of the month, and with tm_isdst unknown, works out whether DST
+ * applies at that midnight, so days around a DST change aren't 24 hours */
+fn day_start(tm: &libc::tm, n: i32) -> Option<SystemTime> {
+    let mut day = *tm;
+    day.tm_sec = 0;
+    day.tm_min = 0;
+    day.tm_hour = 0;
+    day.tm_mday -= n;
+    day.tm_isdst = -1;
+
+    let t = unsafe { libc::mktime(&mut day) };
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(t).ok()?))
+}
+
+/* the starts of today, yesterday and this week (from Monday), local to now */
+fn boundaries(now: SystemTime) -> [SystemTime; 3] {
+    if let Some(tm) = local_time(now) {
+        let weekday = (tm.tm_wday + 6) % 7;
+        if let [Some(today), Some(yesterday), Some(week)] =
+                [0, 1, weekday].map(|n| day_start(&tm, n)) {
+            return [today, yesterday, week];
+        }
+    }
+
+    /* no local time; use UTC days, which started on a Thursday */
+    let days = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / DAY;
+    let weekday = (days + 3) % 7;
+    [0, 1, weekday].map(|n| UNIX_EPOCH + Duration::from_secs(days.saturating_sub(n) * DAY))
+}
+
+impl Period {
+    pub fn of(t: SystemTime, now: SystemTime) -> Period {
+        let [today, yesterday, week] = boundaries(now);
+
+        if t >= today {
+            Period::Today
+        } else if t >= yesterday {
+            Period::Yesterday
+        } else if t >= week {
+            Period::ThisWeek
+        } else {
+            Period::Earlier
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Period::Today => "Today",
+            Period::Yesterday => "Yesterday",
+            Period::ThisWeek => "This week",
+            Period::Earlier => "Earlier",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::sync::Mutex;
+
+    extern "C" {
+        fn tzset();
+    }
+
+    /* the time zone is process-wide, so tests using it take turns */
+    static TZ: Mutex<()> = Mutex::new(());
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    /* check the periods of (utc time, period) cases, at now, in a zone */
+    fn check(zone: &str, now: u64, cases: &[(u64, Period)]) {
+        let _guard = TZ.lock().unwrap_or_else(|e| e.into_inner());
+        env::set_var("TZ", zone);
+        unsafe { tzset() };
+
+        for (t, period) in cases {
+            assert_eq!(Period::of(at(*t), at(now)), *period, "{} at {} in {}", t, now, zone);
+        }
+    }
+
+    #[test]
+    fn boundaries() {
+        /* Thursday 2021-06-10, 12:00 CEST */
+        check("Europe/Berlin", 1623319200, &[
+            (1623319200, Period::Today),
+            (1623276000, Period::Today),
+            (1623276000 - 1, Period::Yesterday),
+            (1623276000 - DAY, Period::Yesterday),
+            (1623276000 - DAY - 1, Period::ThisWeek),
+            /* Monday 00:00 CEST */
+            (1623016800, Period::ThisWeek),
+            (1623016800 - 1, Period::Earlier),
+            (0, Period::Earlier),
+        ]);
+    }
+
+    #[test]
+    fn dst_start() {
+        /* Monday 2021-03-29, 10:00 CEST, after the 23-hour Sunday */
+        check("Europe/Berlin", 1617004800, &[
+            /* Sunday 00:00 CET, 25 hours before today began */
+            (1616886000, Period::Yesterday),
+            (1616884200, Period::Earlier),
+        ]);
+    }
+
+    #[test]
+    fn dst_end() {
+        /* Monday 2021-11-01, 10:00 CET, after the 25-hour Sunday */
+        check("Europe/Berlin", 1635757200, &[
+            /* Sunday 00:30 CEST, 24.5 hours before today began */
+            (1635633000, Period::Yesterday),
+            (1635631140, Period::Earlier),
+        ]);
+    }
+
+    #[test]
+    fn utc() {
+        /* Thursday 2021-06-10, 10:00 UTC */
+        check("UTC", 1623319200, &[
+            (1623283200, Period::Today),
+            (1623283200 - 1, Period::Yesterday),
+            (1623024000, Period::ThisWeek),
+            (1623024000 - 1, Period::Earlier),
+        ]);
+    }
+
+    #[test]
+    fn labels() {
+        assert_eq!(Period::ThisWeek.label(), "This week");
+        assert!(Period::Today < Period::Earlier);
+    }
+}
@@ -0,0 +1,155 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Contacts and calendar events, from Tracker endpoints exporting nco/ncal
+ * data
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use std::process::{Command, Stdio};
+use anyhow::{anyhow, Context};
+
+use crate::config::Config;
+use crate::sparql::{self, Select};
+use crate::tracker;
+
+/* number of results to show */
+const RESULT_LIMIT: usize = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Contacts,
+    Events,
+}
+
+#[derive(Debug)]
+pub struct Item {
+    /* the identifier the application uses to open the item */
+    pub id: String,
+    pub text: String,
+}
+
+impl Kind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::Contacts => "contacts",
+            Kind::Events => "events",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Kind> {
+        match name {
+            "contacts" => Some(Kind::Contacts),
+            "events" => Some(Kind::Events),
+            _ => None,
+        }
+    }
+
+    /* a :contacts or :events command, and its search text */
+    pub fn parse_command(query: &str) -> Option<(Kind, &str)> {
+        let q = query.trim_start().strip_prefix(':')?;
+        let (cmd, text) = q.split_once(char::is_whitespace).unwrap_or((q, ""));
+        Some((Kind::from_name(cmd)?, text.trim()))
+    }
+
+    fn class(&self) -> &'static str {
+        match self {
+            Kind::Contacts => "nco:PersonContact",
+            Kind::Events => "ncal:Event",
+        }
+    }
+
+    /* query for (id, label, detail) of matching items */
+    fn query(&self, text: &str) -> anyhow::Result<String> {
+        let matches = |var: &str| format!("CONTAINS(LCASE({}), LCASE({}))",
+                                          var, sparql::literal(text));
+        let q = match self {
+            /* gnome-contacts can only open contacts by searching by name */
+            Kind::Contacts => Select::new(&["?name", "(?name AS ?label)", "?email"])
+                .pattern("?c a nco:PersonContact ; nco:fullname ?name .")
+                .optional("?c nco:hasEmailAddress/nco:emailAddress ?email .")
+                .filter(&format!("{} || {}", matches("?name"),
+                                 matches(r#"COALESCE(?email, "")"#)))
+                .order_asc("?name"),
+            Kind::Events => Select::new(&["?uid", "?summary", "?start"])
+                .pattern("?e a ncal:Event ; ncal:summary ?summary ; ncal:uid ?uid .")
+                .optional("?e ncal:dtstart/ncal:dateTime ?start .")
+                .filter(&matches("?summary"))
+                .order_desc("?start"),
+        };
+        q.limit(RESULT_LIMIT).build()
+    }
+
+    /* run the application for an item */
+    pub fn open(&self, id: &str) -> anyhow::Result<()> {
+        let mut cmd = match self {
+            Kind::Contacts => {
+                let mut c = Command::new("gnome-contacts");
+                c.arg("--search").arg(id);
+                c
+            }
+            Kind::Events => {
+                let mut c = Command::new("gnome-calendar");
+                c.arg("--uuid").arg(id);
+                c
+            }
+        };
+
+        cmd.stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| format!("can't run {:?}", cmd.get_program()))?;
+        Ok(())
+    }
+}
+
+/* whether a service's endpoint has any items of a kind */
+fn has_items(service: &str, kind: Kind) -> bool {
+    let query = Select::new(&["?x"])
+        .pattern(&format!("?x a {} .", kind.class()))
+        .limit(1)
+        .build();
+
+    match query.and_then(|q| tracker::query_service_v3(service, &q)) {
+        Ok((_, buf)) => !buf.is_empty(),
+        Err(_) => false,
+    }
+}
+
+/* find an endpoint with items of a kind: from those configured, then any
+ * others on the session bus */
+pub fn find_endpoint(kind: Kind, config: &Config) -> Option<String> {
+    let found = tracker::endpoint_services().unwrap_or_default();
+
+    config.endpoints
+        .iter()
+        .chain(found.iter())
+        .find(|s| has_items(s, kind))
+        .cloned()
+}
+
+pub fn search(service: &str, kind: Kind, text: &str) -> anyhow::Result<Vec<Item>> {
+    let (columns, buf) = tracker::query_service_v3(service, &kind.query(text)?)?;
+    if columns.len() != 3 {
+        return Err(anyhow!("Invalid {} search results", kind.name()));
+    }
+
+    let (_, rows) = nom::multi::many0(tracker::parse_row)(buf.as_slice())
+        .map_err(|_| anyhow!("Invalid {} search results", kind.name()))?;
+
+    Ok(rows
+        .iter()
+        .filter_map(|r| {
+            let (id, label, detail) = match r.as_slice() {
+                [id, label, detail] => (id, label, detail),
+                _ => return None,
+            };
+            let text = if detail.is_empty() {
+                label.to_string()
+            } else {
+                format!("{} [{}]", label, detail)
+            };
+            Some(Item { id: id.to_string(), text })
+        })
+        .collect())
+}
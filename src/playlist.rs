@@ -0,0 +1,46 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* M3U playlists from audio results
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use std::env;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use anyhow::Context;
+use url::Url;
+
+/* the playlist file; we only need one at a time, so it's rewritten on each
+ * use */
+pub fn path() -> PathBuf {
+    let dir = env::var_os("XDG_RUNTIME_DIR")
+        .map_or_else(env::temp_dir, PathBuf::from);
+    dir.join("tracker-rofi-playlist.m3u")
+}
+
+/* write an extended M3U playlist of (title, uri) tracks. Local files are
+ * written as paths, which more players understand than file:// URIs */
+pub fn write(tracks: &[(Option<&str>, &Url)]) -> anyhow::Result<PathBuf> {
+    let path = path();
+    let f = File::create(&path)
+        .with_context(|| format!("can't create {}", path.display()))?;
+    let mut f = BufWriter::new(f);
+
+    writeln!(f, "#EXTM3U")?;
+    for (title, uri) in tracks {
+        if let Some(t) = title {
+            /* newlines would break the line-based format */
+            writeln!(f, "#EXTINF:-1,{}", t.replace('\n', " "))?;
+        }
+        match uri.to_file_path() {
+            Ok(p) => f.write_all(p.as_os_str().as_bytes())?,
+            Err(_) => f.write_all(uri.as_str().as_bytes())?,
+        }
+        f.write_all(b"\n")?;
+    }
+    f.flush()?;
+
+    Ok(path)
+}
@@ -0,0 +1,38 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* The current project, for :here searches
+ *
+ * The project is found from a starting path: TRACKER_ROFI_PROJECT in the
+ * environment, which a window manager can set from the focused window, or
+ * the configured `project`. Its root is the nearest directory at or above
+ * that path with a marker file, or the path itself if there's no marker.
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use std::env;
+use std::path::PathBuf;
+
+use crate::config::{expand_path, Config};
+
+pub const PROJECT_ENV: &str = "TRACKER_ROFI_PROJECT";
+
+const MARKERS: &[&str] = &[".tracker-rofi", ".git"];
+
+fn start(config: &Config) -> Option<PathBuf> {
+    match env::var(PROJECT_ENV) {
+        Ok(p) if !p.is_empty() => Some(expand_path(&p)),
+        _ => config.project.clone(),
+    }
+}
+
+pub fn root(config: &Config) -> Option<PathBuf> {
+    let start = start(config)?;
+    let dir = if start.is_dir() { start } else { start.parent()?.to_path_buf() };
+
+    let marked = dir
+        .ancestors()
+        .find(|d| MARKERS.iter().any(|m| d.join(m).exists()))
+        .map(|d| d.to_path_buf());
+
+    Some(marked.unwrap_or(dir))
+}
@@ -0,0 +1,206 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Rofi's script protocol
+ *
+ * Each line of output is a row: its text, then optionally a NUL and a list
+ * of `key\x1fvalue` options, also separated by \x1f. Mode options (such as
+ * the prompt) are given as options on a line with empty text. Since the
+ * format uses NUL, \x1f and newline as delimiters, they can't appear in
+ * text or values; we replace newlines with spaces, and drop the others.
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+const OPTIONS_START: u8 = 0;
+const FIELD_SEP: u8 = 0x1f;
+const LINE_END: u8 = b'\n';
+
+/* option keys, for rows and for the mode */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Key {
+    /* row options */
+    Display,
+    Info,
+    Icon,
+    Meta,
+    Nonselectable,
+    Urgent,
+    Active,
+    /* mode options */
+    Data,
+    Prompt,
+    Message,
+    MarkupRows,
+    UseHotKeys,
+}
+
+impl Key {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Key::Display => "display",
+            Key::Info => "info",
+            Key::Icon => "icon",
+            Key::Meta => "meta",
+            Key::Nonselectable => "nonselectable",
+            Key::Urgent => "urgent",
+            Key::Active => "active",
+            Key::Data => "data",
+            Key::Prompt => "prompt",
+            Key::Message => "message",
+            Key::MarkupRows => "markup-rows",
+            Key::UseHotKeys => "use-hot-keys",
+        }
+    }
+}
+
+fn push_escaped(buf: &mut Vec<u8>, s: &[u8]) {
+    for b in s.iter().copied() {
+        match b {
+            OPTIONS_START | FIELD_SEP => (),
+            LINE_END => buf.push(b' '),
+            _ => buf.push(b),
+        }
+    }
+}
+
+/* a line of output, built from its text and options */
+#[derive(Debug)]
+pub struct Line {
+    buf: Vec<u8>,
+    options: usize,
+}
+
+impl Line {
+    pub fn row(text: &str) -> Self {
+        Line::row_bytes(text.as_bytes())
+    }
+
+    /* a row with text that may not be UTF-8, such as a path */
+    pub fn row_bytes(text: &[u8]) -> Self {
+        let mut buf = Vec::new();
+        push_escaped(&mut buf, text);
+        Line { buf, options: 0 }
+    }
+
+    /* a line for mode options, which has no text */
+    pub fn mode() -> Self {
+        Line::row("")
+    }
+
+    pub fn option(mut self, key: Key, value: &str) -> Self {
+        self.buf.push(if self.options == 0 { OPTIONS_START } else { FIELD_SEP });
+        self.buf.extend(key.name().as_bytes());
+        self.buf.push(FIELD_SEP);
+        push_escaped(&mut self.buf, value.as_bytes());
+        self.options += 1;
+        self
+    }
+
+    /* a boolean option; false is rofi's default, so isn't written */
+    pub fn flag(self, key: Key, value: bool) -> Self {
+        if value { self.option(key, "true") } else { self }
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.buf.push(LINE_END);
+        self.buf
+    }
+}
+
+/* a line with a single mode option */
+pub fn mode_option(key: Key, value: &str) -> Vec<u8> {
+    Line::mode().option(key, value).finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_row() {
+        assert_eq!(Line::row("report.pdf").finish(), b"report.pdf\n");
+    }
+
+    #[test]
+    fn row_options() {
+        let line = Line::row("a")
+            .option(Key::Info, "urn:1")
+            .option(Key::Icon, "application-pdf")
+            .option(Key::Meta, "possible duplicate")
+            .finish();
+        assert_eq!(line, b"a\0info\x1furn:1\x1ficon\x1fapplication-pdf\
+                           \x1fmeta\x1fpossible duplicate\n");
+    }
+
+    #[test]
+    fn display_text() {
+        let line = Line::row("report.pdf")
+            .option(Key::Display, "📄 <b>report.pdf</b>")
+            .option(Key::Info, "urn:1")
+            .finish();
+        assert_eq!(line, "report.pdf\0display\x1f📄 <b>report.pdf</b>\x1finfo\x1furn:1\n"
+                   .as_bytes());
+    }
+
+    #[test]
+    fn row_flags() {
+        let line = Line::row("a")
+            .flag(Key::Nonselectable, true)
+            .flag(Key::Urgent, false)
+            .flag(Key::Active, true)
+            .finish();
+        assert_eq!(line, b"a\0nonselectable\x1ftrue\x1factive\x1ftrue\n");
+    }
+
+    #[test]
+    fn no_flags_no_options() {
+        let line = Line::row("a").flag(Key::Urgent, false).finish();
+        assert_eq!(line, b"a\n");
+    }
+
+    #[test]
+    fn mode_options() {
+        assert_eq!(mode_option(Key::Data, "search?query=a"),
+                   b"\0data\x1fsearch?query=a\n");
+        assert_eq!(mode_option(Key::Prompt, "tag"), b"\0prompt\x1ftag\n");
+        assert_eq!(mode_option(Key::Message, "current tags: a, b"),
+                   b"\0message\x1fcurrent tags: a, b\n");
+        assert_eq!(mode_option(Key::MarkupRows, "true"), b"\0markup-rows\x1ftrue\n");
+    }
+
+    #[test]
+    fn hot_keys() {
+        assert_eq!(mode_option(Key::UseHotKeys, "true"), b"\0use-hot-keys\x1ftrue\n");
+    }
+
+    #[test]
+    fn escape_text() {
+        assert_eq!(Line::row("a\nb\0c\x1fd").finish(), b"a bcd\n");
+    }
+
+    #[test]
+    fn escape_values() {
+        let line = Line::row("a")
+            .option(Key::Icon, "x\x1fnonselectable\x1ftrue")
+            .option(Key::Message, "line 1\nline 2")
+            .option(Key::Info, "a\0b")
+            .finish();
+        assert_eq!(line, b"a\0icon\x1fxnonselectabletrue\x1fmessage\x1fline 1 line 2\
+                           \x1finfo\x1fab\n");
+    }
+
+    #[test]
+    fn bytes_row() {
+        assert_eq!(Line::row_bytes(b"caf\xe9\n").finish(), b"caf\xe9 \n");
+    }
+
+    #[test]
+    fn empty_values() {
+        assert_eq!(Line::row("").option(Key::Info, "").finish(), b"\0info\x1f\n");
+    }
+
+    #[test]
+    fn unicode() {
+        let line = Line::row("✓ naïve.txt").option(Key::Meta, "📌").finish();
+        assert_eq!(line, "✓ naïve.txt\0meta\x1f📌\n".as_bytes());
+    }
+}
@@ -0,0 +1,143 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Rows of rofi output, and their attributes
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use crate::rofi::{Key, Line};
+
+/* states of a result, which can be mapped to row styles in the config */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum State {
+    Offline,
+    Missing,
+    Favorite,
+    Recent,
+    Duplicate,
+}
+
+impl State {
+    pub const ALL: [State; 5] = [
+        State::Offline,
+        State::Missing,
+        State::Favorite,
+        State::Recent,
+        State::Duplicate,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            State::Offline => "offline",
+            State::Missing => "missing",
+            State::Favorite => "favorite",
+            State::Recent => "recent",
+            State::Duplicate => "duplicate",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<State> {
+        State::ALL.iter().copied().find(|s| s.name() == name)
+    }
+}
+
+/* rofi's row flags, which themes can style */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flag {
+    Urgent,
+    Active,
+}
+
+impl Flag {
+    pub fn from_name(name: &str) -> Option<Flag> {
+        match name {
+            "urgent" => Some(Flag::Urgent),
+            "active" => Some(Flag::Active),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Style {
+    pub flag: Option<Flag>,
+    pub color: Option<String>,
+}
+
+/* colors are included in pango markup, so only allow names and hex values */
+pub fn valid_color(c: &str) -> bool {
+    let c = c.strip_prefix('#').unwrap_or(c);
+    !c.is_empty() && c.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+#[derive(Debug, Default)]
+pub struct Row {
+    pub text: String,
+    /* text to show instead of the text, which rofi still matches against */
+    pub display: Option<String>,
+    pub info: Option<String>,
+    pub icon: Option<String>,
+    /* extra text that rofi matches against, but doesn't show */
+    pub meta: Option<String>,
+    pub nonselectable: bool,
+    pub urgent: bool,
+    pub active: bool,
+    pub color: Option<String>,
+}
+
+impl Row {
+    pub fn new(text: &str) -> Self {
+        Row { text: text.to_string(), ..Default::default() }
+    }
+
+    /* add a prefix to the text shown */
+    pub fn prefix(&mut self, prefix: &str) {
+        let shown = self.display.as_mut().unwrap_or(&mut self.text);
+        *shown = format!("{} {}", prefix, shown);
+    }
+
+    /* flags accumulate, but the first style with a color wins */
+    pub fn apply_style(&mut self, style: &Style) {
+        match style.flag {
+            Some(Flag::Urgent) => self.urgent = true,
+            Some(Flag::Active) => self.active = true,
+            None => (),
+        }
+        if self.color.is_none() {
+            self.color = style.color.clone();
+        }
+    }
+
+    pub fn format(&self, markup: bool) -> Vec<u8> {
+        let mut shown = self.display.as_ref().unwrap_or(&self.text).clone();
+
+        if markup {
+            shown = escape_markup(&shown);
+            if let Some(c) = &self.color {
+                shown = format!(r#"<span foreground="{}">{}</span>"#, c, shown);
+            }
+        }
+
+        let mut line = match self.display {
+            Some(_) => Line::row(&self.text).option(Key::Display, &shown),
+            None => Line::row(&shown),
+        };
+        if let Some(info) = &self.info {
+            line = line.option(Key::Info, info);
+        }
+        if let Some(icon) = &self.icon {
+            line = line.option(Key::Icon, icon);
+        }
+        if let Some(meta) = &self.meta {
+            line = line.option(Key::Meta, meta);
+        }
+
+        line.flag(Key::Nonselectable, self.nonselectable)
+            .flag(Key::Urgent, self.urgent)
+            .flag(Key::Active, self.active)
+            .finish()
+    }
+}
+
+fn escape_markup(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
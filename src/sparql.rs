@@ -0,0 +1,321 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Building SPARQL queries
+ *
+ * Queries are assembled from parts, so that the different search features
+ * can each add their own patterns and filters. Since parts are plain text,
+ * a mistake in one (or a missed escape) could produce a query that means
+ * something other than intended; we check bracket and quote balance before
+ * a query is used.
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use anyhow::anyhow;
+
+/* escape a string for use in a quoted SPARQL literal */
+pub fn escape(s: &str) -> String {
+    s
+        .replace('\\', r#"\\"#)
+        .replace('"',  r#"\""#)
+        .replace('\'', r#"\'"#)
+}
+
+/* a quoted SPARQL string literal */
+pub fn literal(s: &str) -> String {
+    format!("\"{}\"", escape(s))
+}
+
+/* names in prefix:name form, as used for properties and graphs */
+pub fn valid_prefixed_name(p: &str) -> bool {
+    match p.split_once(':') {
+        Some((prefix, name)) => {
+            let ok = |s: &str| !s.is_empty() &&
+                s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            ok(prefix) && ok(name)
+        }
+        None => false,
+    }
+}
+
+/* a graph name, from either its full prefixed name, or just the name of
+ * one of tracker's own graphs (eg. "Documents" for tracker:Documents) */
+pub fn graph_name(s: &str) -> Option<String> {
+    let name = if s.contains(':') {
+        s.to_string()
+    } else {
+        format!("tracker:{}", s)
+    };
+    if valid_prefixed_name(&name) { Some(name) } else { None }
+}
+
+/* a pattern that restricts ?var to resources in any of a set of graphs */
+pub fn graph_pattern(var: &str, graphs: &[String]) -> String {
+    graphs
+        .iter()
+        .map(|g| format!("{{ GRAPH {} {{ {} a ?graphtype . }} }}", g, var))
+        .collect::<Vec<_>>()
+        .join(" UNION ")
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Select {
+    distinct: bool,
+    columns: Vec<String>,
+    patterns: Vec<String>,
+    group: Vec<String>,
+    order: Vec<String>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+impl Select {
+    pub fn new(columns: &[&str]) -> Self {
+        Select {
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn distinct(mut self) -> Self {
+        self.distinct = true;
+        self
+    }
+
+    /* a pattern in the WHERE clause; patterns are joined with newlines,
+     * so must include their own terminating '.', where needed */
+    pub fn pattern(mut self, pattern: &str) -> Self {
+        self.patterns.push(pattern.to_string());
+        self
+    }
+
+    pub fn optional(self, pattern: &str) -> Self {
+        self.pattern(&format!("OPTIONAL {{ {} }}", pattern))
+    }
+
+    pub fn filter(self, expr: &str) -> Self {
+        self.pattern(&format!("FILTER ({})", expr))
+    }
+
+    pub fn group_by(mut self, expr: &str) -> Self {
+        self.group.push(expr.to_string());
+        self
+    }
+
+    pub fn order_asc(mut self, expr: &str) -> Self {
+        self.order.push(format!("ASC({})", expr));
+        self
+    }
+
+    pub fn order_desc(mut self, expr: &str) -> Self {
+        self.order.push(format!("DESC({})", expr));
+        self
+    }
+
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /* the query text, once validated */
+    pub fn build(&self) -> anyhow::Result<String> {
+        if self.columns.is_empty() {
+            return Err(anyhow!("query has no columns"));
+        }
+
+        let mut q = String::from("SELECT ");
+        if self.distinct {
+            q += "DISTINCT ";
+        }
+        q += &self.columns.join(" ");
+
+        q += "\nWHERE {\n";
+        for p in &self.patterns {
+            q += p;
+            q += "\n";
+        }
+        q += "}";
+
+        if !self.group.is_empty() {
+            q += &format!("\nGROUP BY {}", self.group.join(" "));
+        }
+        if !self.order.is_empty() {
+            q += &format!("\nORDER BY {}", self.order.join(" "));
+        }
+        if let Some(o) = self.offset {
+            q += &format!("\nOFFSET {}", o);
+        }
+        if let Some(l) = self.limit {
+            q += &format!("\nLIMIT {}", l);
+        }
+
+        validate(&q)?;
+        Ok(q)
+    }
+}
+
+/* check that brackets are balanced, and strings terminated */
+pub fn validate(query: &str) -> anyhow::Result<()> {
+    let mut brackets = Vec::new();
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (i, c) in query.char_indices() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '(' | '{' | '[' => brackets.push((c, i)),
+            ')' | '}' | ']' => {
+                let open = match c {
+                    ')' => '(',
+                    '}' => '{',
+                    _ => '[',
+                };
+                match brackets.pop() {
+                    Some((o, _)) if o == open => (),
+                    Some((o, j)) => return Err(anyhow!(
+                        "mismatched '{}' at offset {}, for '{}' at offset {}",
+                        c, i, o, j)),
+                    None => return Err(anyhow!(
+                        "unbalanced '{}' at offset {}", c, i)),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    if quote.is_some() {
+        return Err(anyhow!("unterminated string"));
+    }
+
+    if let Some((o, j)) = brackets.pop() {
+        return Err(anyhow!("unclosed '{}' at offset {}", o, j));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_literal() {
+        assert_eq!(literal(r#"a "b" c\d 'e'"#), r#""a \"b\" c\\d \'e\'""#);
+    }
+
+    #[test]
+    fn graph_names() {
+        assert_eq!(graph_name("Documents").as_deref(), Some("tracker:Documents"));
+        assert_eq!(graph_name("tracker:Audio").as_deref(), Some("tracker:Audio"));
+        assert_eq!(graph_name("ex:Music").as_deref(), Some("ex:Music"));
+        assert_eq!(graph_name("Docs }"), None);
+        assert_eq!(graph_name("tracker:"), None);
+    }
+
+    #[test]
+    fn graph_patterns() {
+        let graphs = vec!["tracker:Audio".to_string(), "tracker:Video".to_string()];
+        let p = graph_pattern("?s", &graphs);
+        assert_eq!(p, "{ GRAPH tracker:Audio { ?s a ?graphtype . } } UNION \
+                       { GRAPH tracker:Video { ?s a ?graphtype . } }");
+        assert!(validate(&p).is_ok());
+    }
+
+    #[test]
+    fn build_select() {
+        let q = Select::new(&["?s", "?url"])
+            .distinct()
+            .pattern("?s nie:url ?url .")
+            .optional("?s nie:title ?title .")
+            .filter("BOUND(?title)")
+            .order_desc("?title")
+            .offset(10)
+            .limit(5)
+            .build()
+            .unwrap();
+
+        assert_eq!(q, "SELECT DISTINCT ?s ?url\n\
+                       WHERE {\n\
+                       ?s nie:url ?url .\n\
+                       OPTIONAL { ?s nie:title ?title . }\n\
+                       FILTER (BOUND(?title))\n\
+                       }\n\
+                       ORDER BY DESC(?title)\n\
+                       OFFSET 10\n\
+                       LIMIT 5");
+    }
+
+    #[test]
+    fn build_grouped() {
+        let q = Select::new(&["?mime", "(COUNT(?s) AS ?n)"])
+            .pattern("?s nie:mimeType ?mime .")
+            .group_by("?mime")
+            .order_desc("?n")
+            .build()
+            .unwrap();
+
+        assert_eq!(q, "SELECT ?mime (COUNT(?s) AS ?n)\n\
+                       WHERE {\n\
+                       ?s nie:mimeType ?mime .\n\
+                       }\n\
+                       GROUP BY ?mime\n\
+                       ORDER BY DESC(?n)");
+    }
+
+    #[test]
+    fn build_needs_columns() {
+        assert!(Select::new(&[]).build().is_err());
+    }
+
+    #[test]
+    fn build_rejects_unbalanced() {
+        let q = Select::new(&["?s"]).filter("(?a");
+        assert!(q.build().is_err());
+    }
+
+    #[test]
+    fn validate_balanced() {
+        assert!(validate("SELECT ?s WHERE { FILTER ((?a) && [?b]) }").is_ok());
+    }
+
+    #[test]
+    fn validate_brackets_in_strings() {
+        assert!(validate(r#"{ ?s fts:match "a ) } ]" }"#).is_ok());
+        assert!(validate(r#"{ ?s fts:match 'a ( {' }"#).is_ok());
+    }
+
+    #[test]
+    fn validate_escaped_quotes() {
+        assert!(validate(r#"{ ?s fts:match "a \" ( b" }"#).is_ok());
+        assert!(validate(r#"{ ?s fts:match "a \\" ( b" }"#).is_err());
+    }
+
+    #[test]
+    fn validate_unbalanced() {
+        assert!(validate("{ ( }").is_err());
+        assert!(validate("{ ?s }}").is_err());
+        assert!(validate("{ [ ?s ]").is_err());
+        assert!(validate(")").is_err());
+    }
+
+    #[test]
+    fn validate_unterminated_string() {
+        assert!(validate(r#"{ ?s fts:match "abc }"#).is_err());
+        assert!(validate(r#"{ ?s fts:match "abc\" }"#).is_err());
+    }
+}
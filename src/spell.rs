@@ -0,0 +1,200 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Spelling corrections for searches with no results
+ *
+ * Tracker has no fuzzy matching, so a typo gives no results at all. We
+ * correct query words against a vocabulary of words that we know are in
+ * the index: those in the names of files opened from tracker-rofi, and in
+ * previous searches that found something.
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use std::collections::HashSet;
+use percent_encoding::percent_decode_str;
+
+use crate::history;
+
+/* words shorter than this aren't corrected, or used as corrections */
+const MIN_WORD_LEN: usize = 3;
+
+/* the maximum number of corrections to offer */
+const MAX_CORRECTIONS: usize = 3;
+
+fn words(s: &str) -> impl Iterator<Item = String> + '_ {
+    s.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.chars().count() >= MIN_WORD_LEN)
+        .map(str::to_lowercase)
+}
+
+fn vocabulary() -> HashSet<String> {
+    let names = history::opened()
+        .into_iter()
+        .filter_map(|u| {
+            let name = u.rsplit('/').next()?;
+            Some(percent_decode_str(name).decode_utf8_lossy().into_owned())
+        });
+
+    names
+        .chain(history::searches())
+        .flat_map(|s| words(&s).collect::<Vec<_>>())
+        .collect()
+}
+
+/* edit distance, counting a transposition of adjacent characters as a
+ * single edit, as that's a common typo */
+fn distance(a: &str, b: &str) -> usize {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    d[0] = (0..=b.len()).collect();
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
+}
+
+/* the most distant correction we'll make for a word: one edit for short
+ * words, up to two for longer */
+fn max_distance(word: &str) -> usize {
+    if word.chars().count() < 6 { 1 } else { 2 }
+}
+
+/* candidate corrections for a word, closest first */
+fn candidates<'a>(word: &str, vocab: &'a HashSet<String>) -> Vec<&'a str> {
+    let w = word.to_lowercase();
+    let mut c = vocab
+        .iter()
+        .map(|v| (distance(&w, v), v.as_str()))
+        .filter(|(d, _)| *d > 0 && *d <= max_distance(&w))
+        .collect::<Vec<_>>();
+    c.sort();
+    c.into_iter().map(|(_, v)| v).collect()
+}
+
+/* corrected versions of a query, replacing unknown words with close
+ * matches from the vocabulary. Words for which is_term is false (tokens,
+ * operators) are left as they are */
+pub fn corrections<F>(query: &str, is_term: F) -> Vec<String>
+where
+    F: Fn(&str) -> bool
+{
+    correct(query, is_term, &vocabulary())
+}
+
+fn correct<F>(query: &str, is_term: F, vocab: &HashSet<String>) -> Vec<String>
+where
+    F: Fn(&str) -> bool
+{
+    let words = query.split_whitespace().collect::<Vec<_>>();
+
+    /* candidates for each word that we can correct */
+    let fixes = words
+        .iter()
+        .map(|w| {
+            let plain = w.chars().count() >= MIN_WORD_LEN &&
+                w.chars().all(char::is_alphanumeric) && is_term(w);
+            if !plain || vocab.contains(&w.to_lowercase()) {
+                Vec::new()
+            } else {
+                candidates(w, vocab)
+            }
+        })
+        .collect::<Vec<_>>();
+
+    /* alternatives for the first word with corrections, and the best
+     * correction for the others */
+    let first = match fixes.iter().position(|f| !f.is_empty()) {
+        Some(i) => i,
+        None => return Vec::new(),
+    };
+
+    fixes[first]
+        .iter()
+        .take(MAX_CORRECTIONS)
+        .map(|alt| words
+            .iter()
+            .zip(&fixes)
+            .enumerate()
+            .map(|(i, (w, f))| match (i == first, f.first()) {
+                (true, _) => alt,
+                (false, Some(c)) => c,
+                (false, None) => w,
+            })
+            .copied()
+            .collect::<Vec<_>>()
+            .join(" "))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vocab(words: &[&str]) -> HashSet<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn distances() {
+        let cases = [
+            ("report", "report", 0),
+            ("report", "reprot", 1),
+            ("ab", "ba", 1),
+            ("report", "reports", 1),
+            ("report", "repot", 1),
+            ("report", "rapport", 2),
+            ("", "abc", 3),
+            ("café", "cafe", 1),
+            ("kitten", "sitting", 3),
+        ];
+        for (a, b, d) in cases {
+            assert_eq!(distance(a, b), d, "{} to {}", a, b);
+            assert_eq!(distance(b, a), d, "{} to {}", b, a);
+        }
+    }
+
+    #[test]
+    fn word_splitting() {
+        let w = words("Quarterly_Report-2021.pdf a b").collect::<Vec<_>>();
+        assert_eq!(w, ["quarterly", "report", "2021", "pdf"]);
+    }
+
+    #[test]
+    fn corrections() {
+        let v = vocab(&["quarterly", "report", "invoice", "photos", "cat", "car"]);
+        let any = |_: &str| true;
+        let cases: &[(&str, &[&str])] = &[
+            ("quartelry reprot", &["quarterly report"]),
+            ("quarterly report", &[]),
+            ("Reprot", &["report"]),
+            /* short words allow only one edit */
+            ("cxy", &[]),
+            ("cta", &["cat"]),
+            ("invioce", &["invoice"]),
+            ("cas", &["car", "cat"]),
+            ("zzzzzz", &[]),
+            ("title:reprot", &[]),
+            ("ab", &[]),
+        ];
+        for (query, expected) in cases {
+            assert_eq!(correct(query, any, &v), *expected, "{}", query);
+        }
+
+        /* words that aren't terms are left alone */
+        assert_eq!(correct("NAER reprot", |w| w != "NAER", &v), ["NAER report"]);
+    }
+}
@@ -0,0 +1,138 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Query-side stemming
+ *
+ * Tracker's full-text index doesn't give us control over stemming, so
+ * rather than relying on the index matching inflected forms, we strip
+ * common suffixes from query words and search for the stem as an FTS prefix
+ * (eg. "reports" becomes "report*"). This is a "light" stemmer: it only
+ * removes a single inflectional suffix, which is enough for a prefix match
+ * to find the other forms of a word.
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+/* words this short aren't matched by prefix, as short prefixes match too
+ * many unrelated words */
+const MIN_WORD_CHARS: usize = 3;
+
+/* don't reduce words to shorter than this, for the same reason */
+const MIN_STEM_CHARS: usize = 4;
+
+/* a stem must also be longer than the suffix it loses, by this much: long
+ * suffixes taken from short words ("organ-ization", "qual-ité") leave a
+ * root that's shared with unrelated words */
+const MIN_STEM_MARGIN: usize = 2;
+
+/* suffixes for each language, longest first */
+const LANGUAGES: &[(&str, &[&str])] = &[
+    ("en", &["ational", "ization", "fulness", "iveness", "ations", "ation",
+             "ements", "ement", "ments", "ment", "ness", "ings", "ing",
+             "edly", "ies", "ied", "ers", "er", "ed", "es", "ly", "s"]),
+    ("de", &["ungen", "heiten", "keiten", "heit", "keit", "ung", "ern",
+             "em", "en", "er", "es", "e", "s", "n"]),
+    ("fr", &["issements", "issement", "ations", "ation", "ements", "ement",
+             "euses", "euse", "ités", "ité", "ives", "ive", "eux", "ifs",
+             "if", "aux", "es", "er", "ez", "s", "e"]),
+    ("es", &["aciones", "amientos", "amiento", "idades", "ación", "mente",
+             "istas", "ista", "idad", "osos", "osas", "oso", "osa", "es",
+             "os", "as", "ar", "er", "ir", "o", "a", "s"]),
+    ("pt", &["amentos", "amento", "idades", "ações", "mente", "istas",
+             "ista", "idade", "ação", "osos", "osas", "oso", "osa", "es",
+             "os", "as", "ar", "er", "ir", "o", "a", "s"]),
+    ("it", &["azioni", "azione", "amenti", "amento", "mente", "ismi",
+             "ismo", "isti", "ista", "ità", "i", "e", "o", "a"]),
+    ("nl", &["heden", "ingen", "heid", "ing", "en", "e", "s"]),
+];
+
+pub fn valid_language(lang: &str) -> bool {
+    LANGUAGES.iter().any(|(l, _)| *l == lang)
+}
+
+/* the stem of a word, lowercased, or None if the word is too short to
+ * match by prefix */
+pub fn stem(word: &str, lang: &str) -> Option<String> {
+    let word = word.to_lowercase();
+    if word.chars().count() <= MIN_WORD_CHARS {
+        return None;
+    }
+
+    let (_, suffixes) = LANGUAGES.iter().find(|(l, _)| *l == lang)?;
+
+    let stem = suffixes
+        .iter()
+        .filter_map(|s| Some((word.strip_suffix(s)?, s.chars().count())))
+        .find(|(stem, suffix)| {
+            let n = stem.chars().count();
+            n >= MIN_STEM_CHARS && n >= suffix + MIN_STEM_MARGIN
+        })
+        .map(|(stem, _)| stem);
+
+    Some(stem.map_or(word.clone(), str::to_string))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stems() {
+        let cases = [
+            ("en", "reports", "report"),
+            ("en", "Meetings", "meeting"),
+            ("en", "organization", "organiz"),
+            ("en", "organizations", "organization"),
+            ("en", "studies", "studi"),
+            ("en", "brightness", "bright"),
+            ("de", "Rechnungen", "rechnung"),
+            ("de", "Bilder", "bild"),
+            ("fr", "établissements", "établiss"),
+            ("fr", "heureuse", "heureus"),
+            ("fr", "qualités", "qualité"),
+            ("es", "canciones", "cancion"),
+            ("es", "facturas", "factur"),
+            ("es", "ciudades", "ciudad"),
+            ("pt", "cidades", "cidad"),
+            ("pt", "relatórios", "relatóri"),
+            ("it", "relazione", "relazion"),
+            ("it", "documenti", "document"),
+            ("nl", "vergaderingen", "vergader"),
+            ("nl", "rekeningen", "rekening"),
+        ];
+        for (lang, word, stem_) in cases {
+            assert_eq!(stem(word, lang).as_deref(), Some(stem_), "{} ({})", word, lang);
+        }
+    }
+
+    #[test]
+    fn short_roots() {
+        /* removing these suffixes would leave a root shared with unrelated
+         * words: "organ", "rel", "qual", "frei" */
+        let cases = [
+            ("en", "relational"),
+            ("en", "quality"),
+            ("de", "freiheit"),
+            ("fr", "qualité"),
+            ("it", "qualità"),
+            ("es", "lentamente"),
+        ];
+        for (lang, word) in cases {
+            assert_eq!(stem(word, lang).as_deref(), Some(word), "{} ({})", word, lang);
+        }
+    }
+
+    #[test]
+    fn short_words() {
+        assert_eq!(stem("cats", "en").as_deref(), Some("cats"));
+        assert_eq!(stem("Ärger", "de").as_deref(), Some("ärger"));
+        assert_eq!(stem("bed", "en"), None);
+    }
+
+    #[test]
+    fn languages() {
+        for lang in ["en", "de", "fr", "es", "pt", "it", "nl"] {
+            assert!(valid_language(lang));
+        }
+        assert!(!valid_language("xx"));
+        assert_eq!(stem("reports", "xx"), None);
+    }
+}
@@ -0,0 +1,109 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Traces of invocations, for reproducing problems
+ *
+ * With --record <dir>, each invocation writes a file to dir, named by the
+ * time and pid, recording its arguments, rofi's environment, the SPARQL
+ * queries it runs and the output it gives rofi. Each line is a record
+ * type, then its fields as quoted strings, with non-printable and
+ * non-ASCII bytes escaped as \xNN:
+ *
+ *   argv "tracker-rofi" "--record" "/tmp/trace"
+ *   env "ROFI_RETV" "1"
+ *   query "org.freedesktop.Tracker3.Miner.Files" "SELECT ..."
+ *   query "tracker3" "SELECT ..."
+ *   cached "SELECT ..."
+ *   out "report.pdf\x00info\x1furn:..."
+ *   error "failed search for ..."
+ *
+ * Queries run with the tracker3 CLI, when the endpoint isn't reachable,
+ * are recorded with "tracker3" as the service. The out records,
+ * concatenated, are exactly the output rofi read.
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static TRACE: Mutex<Option<File>> = Mutex::new(None);
+
+fn field(s: &[u8]) -> String {
+    format!("\"{}\"", s.escape_ascii())
+}
+
+fn record(kind: &str, fields: &[&[u8]]) {
+    let mut trace = match TRACE.lock() {
+        Ok(t) => t,
+        Err(_) => return,
+    };
+    if let Some(f) = trace.as_mut() {
+        let mut line = String::from(kind);
+        for s in fields {
+            line.push(' ');
+            line += &field(s);
+        }
+        line.push('\n');
+        /* tracing is best-effort; don't fail the invocation for it */
+        let _ = f.write_all(line.as_bytes());
+    }
+}
+
+/* start tracing this invocation into a new file in dir */
+pub fn start(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let name = format!("{}.{:09}-{}.trace", time.as_secs(), time.subsec_nanos(),
+                       process::id());
+    let file = File::create(dir.join(name))?;
+
+    if let Ok(mut t) = TRACE.lock() {
+        *t = Some(file);
+    }
+
+    let argv = env::args_os().collect::<Vec<_>>();
+    record("argv", &argv.iter().map(|a| a.as_bytes()).collect::<Vec<_>>());
+
+    let mut vars = env::vars_os()
+        .filter(|(k, _)| k.as_bytes().starts_with(b"ROFI_"))
+        .collect::<Vec<_>>();
+    vars.sort();
+    for (k, v) in &vars {
+        record("env", &[k.as_bytes(), v.as_bytes()]);
+    }
+    Ok(())
+}
+
+pub fn query(service: &str, query: &str) {
+    record("query", &[service.as_bytes(), query.as_bytes()]);
+}
+
+/* a query answered from the result cache */
+pub fn cached(query: &str) {
+    record("cached", &[query.as_bytes()]);
+}
+
+pub fn error(err: &anyhow::Error) {
+    record("error", &[format!("{:#}", err).as_bytes()]);
+}
+
+/* a writer recording everything written through it */
+pub struct Recorded<W: Write>(pub W);
+
+impl<W: Write> Write for Recorded<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.0.write(buf)?;
+        record("out", &[&buf[..n]]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
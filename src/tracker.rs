@@ -0,0 +1,270 @@
+/* SPDX-License-Identifier: GPL-3.0-or-later */
+/* Queries on the Tracker miner endpoint
+ *
+ * Copyright (c) 2021 Jeremy Kerr <jk@ozlabs.org>
+ */
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::Read;
+use std::thread;
+use std::time::Duration;
+use dbus::blocking::Connection;
+use dbus::Message;
+use dbus::arg::Variant;
+use fd::Pipe;
+
+use nom::number::complete::u32;
+use nom::multi::count;
+
+use crate::cache::Cache;
+use crate::child;
+use crate::cli;
+use crate::trace;
+
+pub const MINER_FILES_SERVICE: &str = "org.freedesktop.Tracker3.Miner.Files";
+pub const ENDPOINT_PATH: &str = "/org/freedesktop/Tracker3/Endpoint";
+pub const ENDPOINT_INTERFACE: &str = "org.freedesktop.Tracker3.Endpoint";
+
+pub const DBUS_TIMEOUT: Duration = Duration::from_millis(2000);
+
+/* D-Bus errors that may resolve by themselves, typically while the session
+ * services are still starting up after login */
+const TRANSIENT_DBUS_ERRORS: &[&str] = &[
+    "org.freedesktop.DBus.Error.NameHasNoOwner",
+    "org.freedesktop.DBus.Error.ServiceUnknown",
+    "org.freedesktop.DBus.Error.Disconnected",
+    "org.freedesktop.DBus.Error.Spawn.",
+];
+
+/* D-Bus errors from failing to connect to the session bus at all, such as
+ * when there's no bus, or it can't be autolaunched. These won't resolve by
+ * retrying, but the tracker3 CLI may still work */
+const CONNECTION_DBUS_ERRORS: &[&str] = &[
+    "org.freedesktop.DBus.Error.NoServer",
+    "org.freedesktop.DBus.Error.FileNotFound",
+    "org.freedesktop.DBus.Error.AccessDenied",
+    "org.freedesktop.DBus.Error.NotSupported",
+    "org.freedesktop.DBus.Error.BadAddress",
+];
+
+/* delays between retries of transient failures */
+const DBUS_RETRY_DELAYS: &[Duration] = &[
+    Duration::from_millis(100),
+    Duration::from_millis(200),
+    Duration::from_millis(400),
+    Duration::from_millis(800),
+];
+
+/* limits on the cursor data we'll accept; we run on every keystroke, so
+ * an unexpectedly large result (say, from a pathological snippet) should
+ * give a truncated result set rather than a large allocation */
+const MAX_CURSOR_BYTES: u64 = 4 * 1024 * 1024;
+const MAX_COLUMNS: u32 = 64;
+const MAX_FIELD_LEN: usize = 64 * 1024;
+
+fn parse_error(buf: &[u8]) -> nom::Err<nom::error::Error<&[u8]>> {
+    nom::Err::Error(nom::error::Error::new(buf, nom::error::ErrorKind::Verify))
+}
+
+/* a field value, truncated to MAX_FIELD_LEN bytes on a character boundary.
+ * Values should be UTF-8, but we'd rather show a replacement character than
+ * lose the row */
+fn field(b: &[u8]) -> Cow<'_, str> {
+    let s = String::from_utf8_lossy(b);
+    if s.len() <= MAX_FIELD_LEN {
+        return s;
+    }
+    let end = (0..=MAX_FIELD_LEN).rev().find(|i| s.is_char_boundary(*i)).unwrap_or(0);
+    Cow::Owned(s[..end].to_string())
+}
+
+/* parse one row of a cursor: the column count, the column types, the
+ * offsets of the end of each column value, then the nul-terminated values */
+pub fn parse_row(buf: &[u8]) -> nom::IResult<&[u8], Vec<Cow<'_, str>>> {
+    let p = u32(nom::number::Endianness::Native);
+
+    let (b, n) = p(buf)?;
+    if n > MAX_COLUMNS {
+        return Err(parse_error(buf));
+    }
+    let (b, _types) = count(p, n as usize)(b)?;
+    let (mut b, lengths) = count(p, n as usize)(b)?;
+
+    let mut offset = 0;
+    let mut res = Vec::new();
+
+    for l in lengths {
+        let len = l.checked_sub(offset).ok_or_else(|| parse_error(b))?;
+        let (bp, x) = nom::bytes::complete::take(len)(b)?;
+        let (bp, _) = nom::bytes::complete::tag(&[0u8])(bp)?;
+        b = bp;
+        res.push(field(x));
+        offset += len + 1;
+    }
+
+    Ok((b, res))
+}
+
+fn is_dbus_error(err: &anyhow::Error, names: &[&str]) -> bool {
+    match err.downcast_ref::<dbus::Error>().and_then(|e| e.name()) {
+        Some(name) => names.iter().any(|n| name.starts_with(n)),
+        None => false,
+    }
+}
+
+fn is_transient(err: &anyhow::Error) -> bool {
+    is_dbus_error(err, TRANSIENT_DBUS_ERRORS)
+}
+
+fn is_connection_failure(err: &anyhow::Error) -> bool {
+    is_dbus_error(err, CONNECTION_DBUS_ERRORS)
+}
+
+/* run a query on the endpoint of any service, without retries */
+pub fn query_service_v3(service: &str, query: &str)
+        -> anyhow::Result<(Vec<String>, Vec<u8>)> {
+    trace::query(service, query);
+
+    let conn = Connection::new_session()?;
+    let mut pipe = Pipe::new()?;
+    let args : HashMap<&str,Variant<u32>> = HashMap::new();
+
+    let msg = Message::new_method_call(service, ENDPOINT_PATH,
+            ENDPOINT_INTERFACE, "Query")
+        .map_err(|e| anyhow::anyhow!(e))?
+        .append1(query)
+        .append1(pipe.writer)
+        .append1(args);
+
+    let reply = conn.channel().send_with_reply_and_block(msg, DBUS_TIMEOUT)?;
+
+    let columns = reply.read1::<Vec<String>>()?;
+
+    /* a truncated cursor still parses, up to the last complete row */
+    let mut buf = Vec::new();
+    (&mut pipe.reader).take(MAX_CURSOR_BYTES).read_to_end(&mut buf)?;
+
+    Ok((columns, buf))
+}
+
+/* run a query on the files endpoint, returning the column names and the raw
+ * cursor data. Transient D-Bus failures are retried with a backoff; if the
+ * endpoint still isn't reachable, or we can't connect to the session bus,
+ * try the tracker3 CLI. Other errors (such as a bad query) are the
+ * endpoint's answer, so are returned as they are */
+pub fn query_endpoint_v3(query: &str) -> anyhow::Result<(Vec<String>, Vec<u8>)> {
+    let mut delays = DBUS_RETRY_DELAYS.iter();
+
+    loop {
+        match query_service_v3(MINER_FILES_SERVICE, query) {
+            Err(e) if is_transient(&e) => match delays.next() {
+                Some(d) => thread::sleep(*d),
+                None => return cli::query(query).map_err(|_| e),
+            },
+            Err(e) if is_connection_failure(&e) => {
+                return cli::query(query).map_err(|_| e);
+            }
+            res => return res,
+        }
+    }
+}
+
+/* check that a service's endpoint responds to queries */
+pub fn ping(service: &str) -> anyhow::Result<()> {
+    query_service_v3(service, "SELECT ?c WHERE { ?c a rdfs:Class } LIMIT 1")?;
+    Ok(())
+}
+
+/* services on the session bus that may export a Tracker endpoint, other
+ * than the files miner */
+pub fn endpoint_services() -> anyhow::Result<Vec<String>> {
+    let conn = Connection::new_session()?;
+    let proxy = conn.with_proxy("org.freedesktop.DBus", "/org/freedesktop/DBus",
+                                DBUS_TIMEOUT);
+    let (names,): (Vec<String>,) =
+        proxy.method_call("org.freedesktop.DBus", "ListNames", ())?;
+
+    Ok(names
+        .into_iter()
+        .filter(|n| n.starts_with("org.freedesktop.Tracker3.") &&
+                n != MINER_FILES_SERVICE)
+        .collect())
+}
+
+/* as query_endpoint_v3, but using the result cache when available */
+pub fn query_v3(query: &str) -> anyhow::Result<(Vec<String>, Vec<u8>)> {
+    let cache = match Cache::active() {
+        Some(c) => c,
+        None => return query_endpoint_v3(query),
+    };
+
+    if let Some(res) = cache.get(query) {
+        trace::cached(query);
+        return Ok(res);
+    }
+
+    let (columns, buf) = query_endpoint_v3(query)?;
+    /* failing to cache is harmless, we just query again next time */
+    let _ = cache.put(query, &columns, &buf);
+
+    Ok((columns, buf))
+}
+
+/* a query in two stages, for slow endpoints. If the full query's results
+ * are cached, use them; otherwise, return the results of the fast query,
+ * and run the full query in a detached child, caching its results for the
+ * next run. Without a cache daemon, there's nowhere to keep the full
+ * results, so we just run the full query */
+pub fn query_progressive(fast: &str, full: &str)
+        -> anyhow::Result<(Vec<String>, Vec<u8>)> {
+    let cache = match Cache::active() {
+        Some(c) => c,
+        None => return query_endpoint_v3(full),
+    };
+
+    if let Some(res) = cache.get(full) {
+        trace::cached(full);
+        return Ok(res);
+    }
+
+    let res = query_v3(fast)?;
+
+    let _ = child::detach(|| {
+        if let Ok((columns, buf)) = query_endpoint_v3(full) {
+            let _ = cache.put(full, &columns, &buf);
+        }
+    });
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dbus_error(name: &str) -> anyhow::Error {
+        anyhow::Error::new(dbus::Error::new_custom(name, "test"))
+    }
+
+    #[test]
+    fn error_classes() {
+        let cases = [
+            ("org.freedesktop.DBus.Error.ServiceUnknown", true, false),
+            ("org.freedesktop.DBus.Error.Spawn.ChildExited", true, false),
+            ("org.freedesktop.DBus.Error.NoServer", false, true),
+            ("org.freedesktop.DBus.Error.NotSupported", false, true),
+            ("org.freedesktop.DBus.Error.AccessDenied", false, true),
+            ("org.freedesktop.Tracker.Error.Parse", false, false),
+            ("org.freedesktop.DBus.Error.InvalidArgs", false, false),
+        ];
+        for (name, transient, connection) in cases {
+            let e = dbus_error(name);
+            assert_eq!(is_transient(&e), transient, "{}", name);
+            assert_eq!(is_connection_failure(&e), connection, "{}", name);
+        }
+
+        let other = anyhow::anyhow!("not a D-Bus error");
+        assert!(!is_transient(&other) && !is_connection_failure(&other));
+    }
+}